@@ -1 +1 @@
-from .wrapper import Benchmark, Result
+from .wrapper import Benchmark, Result, CommandResult, Sample, SelfBenchmarkResult, self_benchmark
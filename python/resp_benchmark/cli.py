@@ -1,7 +1,15 @@
 import argparse
+import sys
 from importlib.metadata import version
 
-from resp_benchmark.wrapper import Benchmark
+from resp_benchmark.wrapper import Benchmark, self_benchmark
+
+
+def seed(value: str) -> int:
+    seed = int(value)
+    if not 0 <= seed < 2 ** 64:
+        raise argparse.ArgumentTypeError(f"must be between 0 and 2^64 - 1, got {seed}")
+    return seed
 
 
 def parse_args():
@@ -19,24 +27,64 @@ def parse_args():
     parser.add_argument("--cluster", action="store_true", help="Use cluster mode (default false)")
     parser.add_argument("-n", metavar="requests", type=int, default=0, help="Total number of requests (default 0), 0 for unlimited.")
     parser.add_argument("-s", metavar="seconds", type=int, default=0, help="Total time in seconds (default 0), 0 for unlimited.")
+    parser.add_argument("--warmup", metavar="seconds", type=int, default=0, help="Seconds to run before measuring, excluded from the results (default 0)")
     parser.add_argument("-P", metavar="pipeline", type=int, default=1, help="Pipeline <numreq> requests. Default 1 (no pipeline).")
-    # parser.add_argument("--tls", action="store_true", help="Use TLS for connection (default false)")
+    parser.add_argument("--tls", action="store_true", help="Use TLS for connection (default false)")
+    parser.add_argument("--cacert", type=str, default="", help="CA certificate file to verify the server with (default system roots)")
+    parser.add_argument("--cert", type=str, default="", help="Client certificate file for mutual TLS")
+    parser.add_argument("--key", type=str, default="", help="Client private key file for mutual TLS")
+    parser.add_argument("--sni", type=str, default="", help="Server name for TLS SNI (default the host, not supported with --cluster)")
+    parser.add_argument("--insecure", action="store_true", help="Allow insecure TLS connection by skipping cert validation")
+    parser.add_argument("--rate", type=float, default=0, help="Target total requests per second, sent open-loop on a schedule (default 0, closed-loop)")
+    parser.add_argument("--rate-distribution", choices=["fixed", "poisson"], default="fixed", help="Spacing of requests with --rate (default fixed)")
+    parser.add_argument("--histogram-digits", type=int, default=3, help="Significant digits of recorded latencies, 1 to 5 (default 3)")
+    parser.add_argument("--histogram-max-seconds", type=int, default=600, help="Highest latency tracked by the histogram in seconds (default 600)")
+    parser.add_argument("--max-error-rate", type=float, default=0.0, help="Abort when the fraction of failed requests exceeds this (default 0, abort on any error, also during the -c 0 ramp-up and the warmup)")
+    parser.add_argument("--seed", type=seed, default=None, help="Seed of the random keys, values and command choices, for reproducible runs (default random)")
+    parser.add_argument("--shard", metavar="I/N", type=str, default=None, help="Run the I-th of N parts of -n, the sequences continue where part I-1 stops, e.g. to load from several machines")
+    parser.add_argument("--output", choices=["human", "json", "csv"], default="human", help="Output format, json and csv print the case, per-second samples and the result (default human)")
     parser.add_argument("--load", action="store_true", help="Only load data to Redis, no benchmark.")
+    parser.add_argument("--self-bench", action="store_true", help="Measure how fast this client generates the commands on one core, without a server (-s seconds, default 3)")
     parser.add_argument('-v', '--version', action='version', version=version('resp_benchmark'))
     parser.add_argument("--help", action="help", help="Output this help and exit.")
-    parser.add_argument("command", type=str, default="SET {key uniform 100000} {value 64}", nargs="?", help="The Redis command to benchmark (default SET {key uniform 100000} {value 64})")
+    parser.add_argument("--weights", type=str, default="", help="Comma-separated weights of the commands for a mixed workload (default equal weights)")
+    parser.add_argument("command", type=str, default=["SET {key uniform 100000} {value 64}"], nargs="*", help="The Redis command(s) to benchmark (default SET {key uniform 100000} {value 64})")
 
     args = parser.parse_args()
+    if args.weights:
+        weights = [int(w) for w in args.weights.split(",")]
+        if len(weights) != len(args.command):
+            parser.error(f"got {len(weights)} weights for {len(args.command)} commands")
+    else:
+        weights = [1] * len(args.command)
+    duplicates = sorted({c for c in args.command if args.command.count(c) > 1})
+    if duplicates:
+        parser.error(f"duplicate commands: {', '.join(duplicates)}, give a command once with the sum of its weights")
+    args.command = dict(zip(args.command, weights))
     return args
 
 
 def main():
     args = parse_args()
-    bm = Benchmark(host=args.h, port=args.p, username=args.u, password=args.a, cluster=args.cluster, cores=args.cores, timeout=30)
-    if args.load:
-        bm.load_data(command=args.command, connections=args.c, pipeline=args.P, count=args.n)
-    else:
-        bm.bench(command=args.command, connections=args.c, pipeline=args.P, count=args.n, seconds=args.s)
+    if args.self_bench:
+        try:
+            result = self_benchmark(command=args.command, pipeline=args.P, seconds=args.s or 3)
+        except ValueError as e:
+            sys.exit(f"error: {e}")
+        print(f"redis::Cmd: {result.cmd_qps:.0f} requests/s, pre-encoded: {result.encoded_qps:.0f} requests/s ({result.encoded_qps / result.cmd_qps:.1f}x)")
+        return
+    bm = Benchmark(host=args.h, port=args.p, username=args.u, password=args.a, cluster=args.cluster, cores=args.cores, timeout=30,
+                   tls=args.tls, tls_ca_cert=args.cacert, tls_cert=args.cert, tls_key=args.key, tls_sni=args.sni, tls_insecure=args.insecure)
+    try:
+        if args.load:
+            bm.load_data(command=args.command, connections=args.c, pipeline=args.P, count=args.n, max_error_rate=args.max_error_rate, output=args.output,
+                         seed=args.seed, shard=args.shard)
+        else:
+            bm.bench(command=args.command, connections=args.c, pipeline=args.P, count=args.n, seconds=args.s, warmup_seconds=args.warmup, max_error_rate=args.max_error_rate,
+                     rate=args.rate, rate_distribution=args.rate_distribution, histogram_digits=args.histogram_digits, histogram_max_seconds=args.histogram_max_seconds, output=args.output,
+                     seed=args.seed, shard=args.shard)
+    except ValueError as e:
+        sys.exit(f"error: {e}")
 
 
 if __name__ == "__main__":
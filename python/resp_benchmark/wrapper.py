@@ -1,6 +1,6 @@
 import multiprocessing
 from dataclasses import dataclass
-from typing import List
+from typing import Dict, List, Optional, Union
 
 import pydantic
 import redis
@@ -8,6 +8,68 @@ import redis
 from .cores import parse_cores_string
 
 
+@dataclass
+class CommandResult:
+    """
+    Represents the result of one command template in a mixed workload.
+
+    Attributes:
+        command (str): The command template.
+        weight (int): The weight of the template.
+        qps (float): Average queries per second of the template.
+        avg_value_size (float): Average bytes generated by the {value} placeholders of a request.
+        avg_latency_ms (float): Average latency in milliseconds.
+        stddev_latency_ms (float): Standard deviation of latency in milliseconds.
+        min_latency_ms (float): Minimum latency in milliseconds.
+        p50_latency_ms (float): 50th percentile latency in milliseconds.
+        p90_latency_ms (float): 90th percentile latency in milliseconds.
+        p95_latency_ms (float): 95th percentile latency in milliseconds.
+        p99_latency_ms (float): 99th percentile latency in milliseconds.
+        p999_latency_ms (float): 99.9th percentile latency in milliseconds.
+        p9999_latency_ms (float): 99.99th percentile latency in milliseconds.
+        max_latency_ms (float): Maximum latency in milliseconds.
+    """
+    command: str
+    weight: int
+    qps: float
+    avg_value_size: float
+    avg_latency_ms: float
+    stddev_latency_ms: float
+    min_latency_ms: float
+    p50_latency_ms: float
+    p90_latency_ms: float
+    p95_latency_ms: float
+    p99_latency_ms: float
+    p999_latency_ms: float
+    p9999_latency_ms: float
+    max_latency_ms: float
+
+
+@dataclass
+class Sample:
+    """
+    Represents one second of a benchmark.
+
+    Attributes:
+        timestamp (float): Unix time in seconds at the end of the interval.
+        ops (int): The number of successful requests in the interval.
+        qps (float): Queries per second in the interval, the last interval may be shorter than a second.
+        errors (int): The number of failed requests in the interval.
+        p50_latency_ms (float): 50th percentile latency in milliseconds.
+        p99_latency_ms (float): 99th percentile latency in milliseconds.
+        max_latency_ms (float): Maximum latency in milliseconds.
+        connections (int): The number of active connections at the end of the interval.
+    """
+    timestamp: float
+    ops: int
+    qps: float
+    errors: int
+    p50_latency_ms: float
+    p99_latency_ms: float
+    max_latency_ms: float
+    connections: int
+
+
 @dataclass
 class Result:
     """
@@ -15,14 +77,97 @@ class Result:
 
     Attributes:
         qps (float): Average queries per second.
+        avg_value_size (float): Average bytes generated by the {value} placeholders of a request, over all commands.
         avg_latency_ms (float): Average latency in milliseconds.
+        stddev_latency_ms (float): Standard deviation of latency in milliseconds.
+        min_latency_ms (float): Minimum latency in milliseconds.
+        p50_latency_ms (float): 50th percentile latency in milliseconds.
+        p90_latency_ms (float): 90th percentile latency in milliseconds.
+        p95_latency_ms (float): 95th percentile latency in milliseconds.
         p99_latency_ms (float): 99th percentile latency in milliseconds.
+        p999_latency_ms (float): 99.9th percentile latency in milliseconds.
+        p9999_latency_ms (float): 99.99th percentile latency in milliseconds.
+        max_latency_ms (float): Maximum latency in milliseconds.
         connections (int): The number of parallel connections.
+        rate (float): The target queries per second in open-loop mode, 0 for closed-loop. Compare with qps for the achieved rate.
+        errors (int): The number of failed requests.
+        error_kinds (Dict[str, int]): Failed requests by kind, the error reply prefix (e.g. WRONGTYPE, OOM, MOVED)
+            for server errors, or one of timeout, refused, io, other.
+        commands (List[CommandResult]): Results of each command template, in the order given.
+        samples (List[Sample]): Per-second samples of the measured period, e.g. to plot throughput over time.
+        interrupted (bool): Whether the benchmark was stopped early by Ctrl-C, the results cover the time until then.
     """
     qps: float
+    avg_value_size: float
     avg_latency_ms: float
+    stddev_latency_ms: float
+    min_latency_ms: float
+    p50_latency_ms: float
+    p90_latency_ms: float
+    p95_latency_ms: float
     p99_latency_ms: float
+    p999_latency_ms: float
+    p9999_latency_ms: float
+    max_latency_ms: float
     connections: int
+    rate: float
+    errors: int
+    error_kinds: Dict[str, int]
+    commands: List[CommandResult]
+    samples: List[Sample]
+    interrupted: bool
+
+
+@dataclass
+class SelfBenchmarkResult:
+    """
+    Represents the client side cost of generating requests, measured without a server.
+
+    Attributes:
+        cmd_qps (float): Requests per second generated through redis::Cmd, the path of cluster connections.
+        encoded_qps (float): Requests per second encoded straight into the pipeline buffer, the path of standalone connections.
+    """
+    cmd_qps: float
+    encoded_qps: float
+
+
+def self_benchmark(command: Union[str, Dict[str, int]], pipeline: int = 1, seconds: float = 3) -> SelfBenchmarkResult:
+    """
+    Generates pipelines of the commands on one thread as fast as it can, once through each path, to show how many
+    requests per second a core of this client can send at most.
+
+    Args:
+        command (Union[str, Dict[str, int]]): The command template, or a dict of command templates, the weights are ignored.
+        pipeline (int): The number of commands in a pipeline.
+        seconds (float): The duration of each path.
+    Returns:
+        SelfBenchmarkResult: The requests per second of each path.
+    Raises:
+        ValueError: If an option is invalid, or a TemplateError, a ValueError with the offset and expected
+            attributes, if a command template is.
+    """
+    from . import _resp_benchmark_rust_lib
+    commands, _ = _split_weights(command)
+    cmd_qps, encoded_qps = _resp_benchmark_rust_lib.self_benchmark(commands=commands, pipeline=pipeline, seconds=seconds)
+    return SelfBenchmarkResult(cmd_qps=cmd_qps, encoded_qps=encoded_qps)
+
+
+def _latency_fields(ret) -> Dict[str, float]:
+    names = ["avg", "stddev", "min", "p50", "p90", "p95", "p99", "p999", "p9999", "max"]
+    return {f"{name}_latency_ms": getattr(ret, f"{name}_latency_ms") for name in names}
+
+
+def _split_weights(command: Union[str, Dict[str, int]]):
+    if isinstance(command, str):
+        return [command], [1]
+    return list(command.keys()), list(command.values())
+
+
+# The seed is a u64 on the Rust side, which would raise OverflowError outside of it.
+def _check_seed(seed: Optional[int]) -> Optional[int]:
+    if seed is not None and not 0 <= seed < 2 ** 64:
+        raise ValueError(f"seed must be between 0 and 2^64 - 1, got {seed}")
+    return seed
 
 
 class Benchmark:
@@ -36,6 +181,11 @@ class Benchmark:
         password (str): The password for authentication.
         cluster (bool): Whether to connect to a Redis cluster.
         tls (bool): Whether to use TLS for the connection.
+        tls_ca_cert (str): Path to the CA certificate bundle (PEM), system roots are used if empty.
+        tls_cert (str): Path to the client certificate (PEM) for mutual TLS.
+        tls_key (str): Path to the client private key (PEM) for mutual TLS.
+        tls_sni (str): Server name sent in the TLS handshake, defaults to the host. Not supported in cluster mode.
+        tls_insecure (bool): Skip server certificate and hostname verification.
         timeout (int): Timeout for the connection in seconds.
         cores (str): Comma-separated list of CPU cores to use.
     """
@@ -48,7 +198,12 @@ class Benchmark:
             username: str = "",
             password: str = "",
             cluster: bool = False,
-            # tls: bool = False,
+            tls: bool = False,
+            tls_ca_cert: str = "",
+            tls_cert: str = "",
+            tls_key: str = "",
+            tls_sni: str = "",
+            tls_insecure: bool = False,
             timeout: int = 30,
             cores: str = "",
     ):
@@ -57,7 +212,12 @@ class Benchmark:
         self.username = username
         self.password = password
         self.cluster = cluster
-        # self.tls = tls
+        self.tls = tls
+        self.tls_ca_cert = tls_ca_cert
+        self.tls_cert = tls_cert
+        self.tls_key = tls_key
+        self.tls_sni = tls_sni
+        self.tls_insecure = tls_insecure
         self.timeout = timeout
         if cores == "":
             cores = f"0-{multiprocessing.cpu_count() - 1}"
@@ -65,89 +225,186 @@ class Benchmark:
 
     def bench(
             self,
-            command: str,
+            command: Union[str, Dict[str, int]],
             connections: int = 0,
             pipeline: int = 1,
             count: int = 0,
             seconds: int = 0,
             quiet: bool = False,
+            *,
+            warmup_seconds: int = 0,
+            max_error_rate: float = 0.0,
+            rate: float = 0,
+            rate_distribution: str = "fixed",
+            histogram_digits: int = 3,
+            histogram_max_seconds: int = 600,
+            output: str = "human",
+            seed: Optional[int] = None,
+            shard: Optional[str] = None,
     ) -> Result:
         """
         Runs a benchmark test with the specified parameters.
 
         Args:
-            command (str): The Redis command to benchmark.
+            command (Union[str, Dict[str, int]]): The Redis command to benchmark, or a dict of command templates
+                to weights for a mixed workload, e.g. {"GET {key uniform 100}": 80, "SET {key uniform 100} {value 64}": 20}.
             connections (int): The number of parallel connections.
             pipeline (int): The number of commands to pipeline.
             count (int): The total number of requests to make.
             seconds (int): The duration of the test in seconds.
             quiet: (bool): Whether to suppress output.
+            warmup_seconds (int): Run this long before measuring, once connections are ramped up. Latency, QPS and
+                errors exclude the warmup, and seconds counts from its end.
+            max_error_rate (float): The fraction of failed requests above which the test is aborted. The default 0
+                aborts on the first error, also during the connection ramp-up and the warmup.
+            rate (float): The target queries per second of all connections, 0 for closed-loop. In open-loop mode requests
+                are sent on a schedule and latency is measured from the scheduled time, including time spent waiting
+                behind slow requests.
+            rate_distribution (str): How requests are spread in open-loop mode, "fixed" interval or "poisson" arrivals.
+            histogram_digits (int): Significant decimal digits kept for each latency, from 1 to 5. Higher is more precise but uses more memory.
+            histogram_max_seconds (int): The highest latency tracked precisely, longer latencies are counted at this value.
+            output (str): The format printed to stdout, "human" text, or "json" lines and "csv" rows of the case,
+                per-second samples and the result.
+            seed (Optional[int]): Seeds the random keys, values and command choices of every connection, so runs with
+                the same seed and connections send the same requests. Random if None.
+            shard (Optional[str]): "I/N" to run the I-th of N parts of count, e.g. on N client machines. Each part
+                sends its share of the count, and its sequences continue where the part before it stops. Needs a single command, without sequences
+                inside a {repeat} with a count range.
         Returns:
             Result: The results of the benchmark test.
+        Raises:
+            ValueError: If an option is invalid, or a TemplateError, a ValueError with the offset and expected
+                attributes, if a command template is. Its message points at the offending part of the template.
         """
         from . import _resp_benchmark_rust_lib
+        commands, weights = _split_weights(command)
         ret = _resp_benchmark_rust_lib.benchmark(
             host=self.host,
             port=self.port,
             username=self.username,
             password=self.password,
             cluster=self.cluster,
-            tls=False,  # TODO: Implement TLS support
+            tls=self.tls,
+            tls_ca_cert=self.tls_ca_cert,
+            tls_cert=self.tls_cert,
+            tls_key=self.tls_key,
+            tls_sni=self.tls_sni,
+            tls_insecure=self.tls_insecure,
             timeout=self.timeout,
             cores=self.cores,
 
-            command=command,
+            commands=commands,
+            weights=weights,
             connections=connections,
             pipeline=pipeline,
             count=count,
             seconds=seconds,
+            max_error_rate=max_error_rate,
+            rate=rate,
+            rate_distribution=rate_distribution,
+            histogram_digits=histogram_digits,
+            histogram_max_seconds=histogram_max_seconds,
+            warmup_seconds=warmup_seconds,
+            output=output,
             load=False,
             quiet=quiet,
+            seed=_check_seed(seed),
+            shard=shard,
         )
         result = Result(
             qps=ret.qps,
-            avg_latency_ms=ret.avg_latency_ms,
-            p99_latency_ms=ret.p99_latency_ms,
-            connections=ret.connections
+            avg_value_size=ret.avg_value_size,
+            **_latency_fields(ret),
+            connections=ret.connections,
+            rate=ret.rate,
+            errors=ret.errors,
+            error_kinds=ret.error_kinds,
+            commands=[CommandResult(
+                command=c.command,
+                weight=c.weight,
+                qps=c.qps,
+                avg_value_size=c.avg_value_size,
+                **_latency_fields(c),
+            ) for c in ret.commands],
+            samples=[Sample(
+                timestamp=s.timestamp,
+                ops=s.ops,
+                qps=s.qps,
+                errors=s.errors,
+                p50_latency_ms=s.p50_latency_ms,
+                p99_latency_ms=s.p99_latency_ms,
+                max_latency_ms=s.max_latency_ms,
+                connections=s.connections,
+            ) for s in ret.samples],
+            interrupted=ret.interrupted,
         )
 
         return result
 
-    def load_data(self, command: str, count: int, connections: int = 128, pipeline: int = 10, quiet: bool = False):
+    def load_data(self, command: Union[str, Dict[str, int]], count: int, connections: int = 128, pipeline: int = 10, quiet: bool = False, *,
+                  max_error_rate: float = 0.0, output: str = "human", seed: Optional[int] = None, shard: Optional[str] = None):
         """
         Load data into the Redis server using the specified command.
 
         Args:
-            command (str): The Redis command to use for loading data.
+            command (Union[str, Dict[str, int]]): The Redis command to use for loading data, or a dict of command templates to weights.
             count (int): The total number of requests to make.
             connections (int): The number of parallel connections.
             pipeline (int): The number of commands to pipeline
             quiet: (bool): Whether to suppress output.
+            max_error_rate (float): The fraction of failed requests above which loading is aborted, the default 0
+                aborts on the first error.
+            output (str): The format printed to stdout, "human", "json" or "csv".
+            seed (Optional[int]): Seeds the random keys and values, so the same data is loaded on every run. Random if None.
+            shard (Optional[str]): "I/N" to load the I-th of N parts of count, so N loads, e.g. from several machines,
+                together cover the sequences once without writing the same keys. Needs a single command, without
+                sequences inside a {repeat} with a count range.
+        Raises:
+            ValueError: If an option is invalid, or a TemplateError, a ValueError with the offset and expected
+                attributes, if a command template is.
         """
 
         from . import _resp_benchmark_rust_lib
+        commands, weights = _split_weights(command)
         _resp_benchmark_rust_lib.benchmark(
             host=self.host,
             port=self.port,
             username=self.username,
             password=self.password,
             cluster=self.cluster,
-            tls=False,
+            tls=self.tls,
+            tls_ca_cert=self.tls_ca_cert,
+            tls_cert=self.tls_cert,
+            tls_key=self.tls_key,
+            tls_sni=self.tls_sni,
+            tls_insecure=self.tls_insecure,
             timeout=self.timeout,
             cores=self.cores,
 
-            command=command,
+            commands=commands,
+            weights=weights,
             connections=connections,
             pipeline=pipeline,
             count=count,
             seconds=0,
+            max_error_rate=max_error_rate,
+            rate=0,
+            rate_distribution="fixed",
+            histogram_digits=3,
+            histogram_max_seconds=600,
+            warmup_seconds=0,
+            output=output,
             load=True,
             quiet=quiet,
+            seed=_check_seed(seed),
+            shard=shard,
         )
 
     def flushall(self):
         """
         Clears all data from all Redis databases.
         """
-        r = redis.Redis(host=self.host, port=self.port, username=self.username, password=self.password)
+        r = redis.Redis(host=self.host, port=self.port, username=self.username, password=self.password,
+                        ssl=self.tls, ssl_ca_certs=self.tls_ca_cert or None, ssl_certfile=self.tls_cert or None, ssl_keyfile=self.tls_key or None,
+                        ssl_cert_reqs="none" if self.tls_insecure else "required")
         r.flushall()
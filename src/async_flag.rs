@@ -16,6 +16,10 @@ impl AsyncFlag {
         self.receiver.changed().await.unwrap();
     }
 
+    pub fn is_set(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
     pub fn set_flag(&self) {
         self.sender.send(true).unwrap();
     }
@@ -84,7 +84,7 @@ impl AutoConnection {
         let mut total_connection = if auto { MAX_CONN } else { active_conn };
         let mut left_count = thread_count;
         for _ in 0..thread_count {
-            let my_conn = (total_connection + left_count - 1) / left_count;
+            let my_conn = total_connection.div_ceil(left_count);
             let target_conn = if auto { 0 } else { my_conn };
             let conn_limiter = Arc::new(ConnLimiter::new(my_conn, target_conn));
             limiters.push(conn_limiter);
@@ -140,6 +140,5 @@ impl AutoConnection {
         self.last_qps = qps;
         self.last_cnt = h.cnt();
         self.instant = std::time::Instant::now();
-        return;
     }
 }
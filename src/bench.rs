@@ -1,34 +1,61 @@
-use std::io::Write;
 use std::sync::Arc;
 use awaitgroup::WaitGroup;
-use colored::Colorize;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::RngCore;
 use tokio::{select, task};
 
-use crate::BenchmarkResult;
+use crate::{BenchmarkResult, CommandResult, Sample};
 use crate::client::ClientConfig;
 use crate::command::Command;
 use crate::auto_connection::{AutoConnection, ConnLimiter};
+use crate::output::Output;
+use crate::rng::FastRng;
 use crate::shared_context::SharedContext;
 
+// What the signals received since the last poll ask the run to do.
+pub enum Signal {
+    None,
+    Interrupt, // Ctrl-C, the run stops with the results so far and a second one exits
+    Stop,      // e.g. for an exception raised to the caller instead of returning the results
+}
+
 #[derive(Clone)]
 pub struct Case {
-    pub command: Command,
+    pub commands: Vec<Command>,
+    pub weights: Vec<u64>, // one per command, each request picks a command with probability weight / sum
     pub connections: u64,
     pub count: u64,
     pub seconds: u64,
     pub pipeline: u64,
+    pub max_error_rate: f64,
+    pub rate: f64,     // target ops/s of all connections in open-loop mode, 0 for closed-loop
+    pub poisson: bool, // poisson arrivals instead of a fixed interval in open-loop mode
+    pub histogram_digits: u8,       // significant digits kept for each latency
+    pub histogram_max_seconds: u64, // longer latencies are counted at this value
+    pub warmup_seconds: u64,        // run before measuring, after connections are ramped up
+    pub seed: Option<u64>,          // for reproducible random streams, from entropy if not set
 }
 
-async fn run_commands_on_single_thread(limiter: Arc<ConnLimiter>, config: ClientConfig, case: Case, context: SharedContext) {
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+// Connections of the thread are numbered from first_conn_id.
+async fn run_commands_on_single_thread(thread_id: u64, first_conn_id: u64, limiter: Arc<ConnLimiter>, config: ClientConfig, case: Case, context: SharedContext) {
     let local = task::LocalSet::new();
-    for _ in 0..limiter.total_conn {
+    for conn_id in first_conn_id..first_conn_id + limiter.total_conn {
         let limiter = limiter.clone();
         let config = config.clone();
         let case = case.clone();
         let mut context = context.clone();
         local.spawn_local(async move {
             let mut client = config.get_client().await;
-            let mut cmd = case.command.clone();
+            // each connection has its own stream, and each of its commands one derived from it
+            let mut rng = case.seed.map_or_else(FastRng::from_entropy, |seed| FastRng::stream(seed, conn_id));
+            let mut cmds = case.commands.clone();
+            for cmd in cmds.iter_mut() {
+                cmd.set_connection(conn_id, thread_id, rng.next_u64());
+            }
+            let choose = WeightedIndex::new(&case.weights).unwrap();
+            let mut published = vec![(0, 0); cmds.len()]; // the gen_cnt and value_bytes of each command added to context
             let limiter = limiter.clone();
             select! {
                 _ = limiter.wait_new_conn() =>{}
@@ -42,21 +69,69 @@ async fn run_commands_on_single_thread(limiter: Arc<ConnLimiter>, config: Client
                     context.stop();
                     break;
                 }
+                let conn = match &mut client {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        // requests fetched without a connection count as failed, so a dead server shows up in the error rate
+                        context.errors.record(e, pipeline_cnt);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        client = config.get_client().await;
+                        continue;
+                    }
+                };
 
                 // prepare pipeline
-                let mut p = Vec::new();
-                for _ in 0..pipeline_cnt {
-                    if context.is_loading {
-                        p.push(cmd.gen_cmd_with_lock());
-                    } else {
-                        p.push(cmd.gen_cmd());
+                let inxs: Vec<usize> = (0..pipeline_cnt).map(|_| if cmds.len() == 1 { 0 } else { choose.sample(&mut rng) }).collect();
+                for (inx, cmd) in cmds.iter_mut().enumerate() {
+                    cmd.reserve(inxs.iter().filter(|&&i| i == inx).count() as u64);
+                }
+                conn.prepare(&mut cmds, &inxs);
+                // added as they grow, so the part generated before measuring can be left out of the value size
+                for (inx, cmd) in cmds.iter().enumerate() {
+                    let (gen_cnt, value_bytes) = (cmd.gen_cnt(), cmd.value_bytes());
+                    if gen_cnt != published[inx].0 {
+                        context.gen_cnts[inx].fetch_add(gen_cnt - published[inx].0, std::sync::atomic::Ordering::Relaxed);
+                        context.value_bytes[inx].fetch_add(value_bytes - published[inx].1, std::sync::atomic::Ordering::Relaxed);
+                        published[inx] = (gen_cnt, value_bytes);
                     }
                 }
-                let instant = std::time::Instant::now();
-                client.run_commands(p).await;
-                let duration = instant.elapsed().as_micros() as u64;
-                for _ in 0..pipeline_cnt {
-                    context.histogram.record(duration);
+                // In open-loop mode latency counts from the intended send time, so the time a request waits behind
+                // a slow one is included (coordinated omission). Oversleeping the timer is not the server's fault though.
+                let instant = match context.schedule(pipeline_cnt, &mut rng) {
+                    Some(intended) if intended > std::time::Instant::now() => {
+                        select! {
+                            _ = tokio::time::sleep_until(intended.into()) => {}
+                            _ = context.wait_stop() => break,
+                        }
+                        // the run can end while the pipeline waits for its turn, it is dropped then
+                        if context.is_stopped() || context.is_timed_out() {
+                            context.stop();
+                            break;
+                        }
+                        std::time::Instant::now()
+                    }
+                    Some(intended) => intended,
+                    None => std::time::Instant::now(),
+                };
+                let result = conn.run().await;
+                match result {
+                    // error replies count one each, the other requests of the pipeline succeeded
+                    Ok(failed) => {
+                        let duration = instant.elapsed().as_nanos() as u64;
+                        let mut failed = failed.into_iter().peekable();
+                        for (i, inx) in inxs.into_iter().enumerate() {
+                            match failed.next_if(|(failed, _)| *failed == i) {
+                                Some((_, e)) => context.errors.record(&e, 1),
+                                None => context.record(inx, duration),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        context.errors.record(&e, pipeline_cnt);
+                        if e.is_unrecoverable_error() {
+                            client = config.get_client().await;
+                        }
+                    }
                 }
             }
         });
@@ -64,79 +139,186 @@ async fn run_commands_on_single_thread(limiter: Arc<ConnLimiter>, config: Client
     local.await;
 }
 
-fn wait_finish(case: &Case, mut auto_connection: AutoConnection, mut context: SharedContext, mut wg: WaitGroup, quiet: bool) -> BenchmarkResult {
+fn value_counts(context: &SharedContext) -> Vec<(u64, u64)> {
+    let load = |c: &std::sync::atomic::AtomicU64| c.load(std::sync::atomic::Ordering::Relaxed);
+    context.gen_cnts.iter().zip(context.value_bytes.iter()).map(|(gen_cnt, value_bytes)| (load(gen_cnt), load(value_bytes))).collect()
+}
+
+// value bytes per generated command since overhead, the value_counts at measuring start
+fn avg_value_size(context: &SharedContext, overhead: &[(u64, u64)], inxs: std::ops::Range<usize>) -> f64 {
+    let (gen_cnt, value_bytes) = value_counts(context)[inxs.clone()].iter().zip(&overhead[inxs]).fold((0, 0), |(c, b), (now, before)| (c + now.0 - before.0, b + now.1 - before.1));
+    value_bytes as f64 / gen_cnt.max(1) as f64
+}
+
+fn wait_finish(case: &Case, mut auto_connection: AutoConnection, mut context: SharedContext, mut wg: WaitGroup, output: &mut Output, signal: &dyn Fn() -> Signal) -> BenchmarkResult {
     let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
     let mut result = BenchmarkResult::default();
 
     rt.block_on(async {
         let histogram = context.histogram.clone();
+        let errors = context.errors.clone();
         // calc overall qps
         let mut overall_time = std::time::Instant::now();
         let mut overall_cnt_overhead = 0;
+        let mut command_cnt_overhead = vec![0; case.commands.len()];
+        let mut value_overhead = vec![(0, 0); case.commands.len()];
         // for log
         let mut log_instance = std::time::Instant::now();
         let mut log_last_cnt = histogram.cnt();
         let mut interval = tokio::time::interval(std::time::Duration::from_millis(233));
-
-        if auto_connection.ready {
-            context.start_timer();
-            overall_time = std::time::Instant::now();
-            overall_cnt_overhead = 0;
-        }
+        // measuring starts after the auto connection ramp-up and then the warmup
+        let warmup = std::time::Duration::from_secs(case.warmup_seconds);
+        let mut warmup_instant = if auto_connection.ready { Some(std::time::Instant::now()) } else { None };
+        let mut measuring = false;
+        // per-second samples while measuring
+        let mut sample_interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        let mut sample_instant = std::time::Instant::now();
+        let mut sample_last_errors = 0;
 
         loop {
+            if !auto_connection.ready {
+                auto_connection.adjust(&histogram);
+                if auto_connection.ready {
+                    warmup_instant = Some(std::time::Instant::now());
+                }
+            }
+            if !measuring && warmup_instant.is_some_and(|instant| instant.elapsed() >= warmup) {
+                measuring = true;
+                // drop the samples and errors taken so far, loading keeps them for the progress
+                if !context.is_loading {
+                    context.clear_histograms();
+                    errors.clear();
+                    log_last_cnt = 0;
+                }
+                overall_cnt_overhead = histogram.cnt();
+                command_cnt_overhead = context.command_histograms.iter().map(|h| h.cnt()).collect();
+                value_overhead = value_counts(&context);
+                overall_time = std::time::Instant::now();
+                context.start_timer();
+                context.clear_interval_histograms();
+                sample_interval.reset();
+                sample_instant = std::time::Instant::now();
+                sample_last_errors = errors.cnt();
+            }
             select! {
                 _ = interval.tick() => {}
+                _ = sample_interval.tick() => {
+                    if measuring {
+                        let interval_histogram = context.swap_interval_histogram();
+                        let error_cnt = errors.cnt();
+                        let sample = Sample::new(interval_histogram, sample_instant.elapsed().as_secs_f64(), error_cnt - sample_last_errors, auto_connection.active_conn());
+                        interval_histogram.clear();
+                        output.sample(&sample);
+                        result.samples.push(sample);
+                        sample_instant = std::time::Instant::now();
+                        sample_last_errors = error_cnt;
+                    }
+                    continue;
+                }
                 _ = wg.wait() => {break;}
             }
             {
                 let cnt = histogram.cnt();
                 let qps = (cnt - log_last_cnt) as f64 / log_instance.elapsed().as_secs_f64();
                 let conn: u64 = auto_connection.active_conn();
-                if auto_connection.ready {
+                if measuring {
                     result.qps = (cnt - overall_cnt_overhead) as f64 / overall_time.elapsed().as_secs_f64();
                 }
-                if !quiet {
-                    if context.is_loading {
-                        println!("\x1B[F\x1B[2KData loading qps: {:.0}, {:.2}%", qps, histogram.cnt() as f64 / case.count as f64 * 100f64);
-                    } else {
-                        println!("\x1B[F\x1B[2Kqps: {:.0}(overall {:.0}), conn: {}, {}", qps, result.qps, conn, histogram);
-                    }
+                let errors = if errors.cnt() == 0 { String::new() } else { format!(", {}", errors) };
+                if context.is_loading {
+                    output.live(&format!("Data loading qps: {:.0}, {:.2}%{}", qps, histogram.cnt() as f64 / case.count as f64 * 100f64, errors));
+                } else {
+                    let target = if case.rate > 0.0 { format!(", target {:.0}", case.rate) } else { String::new() };
+                    let warming = if warmup_instant.is_some() && !measuring { ", warming up" } else { "" };
+                    output.live(&format!("qps: {:.0}(overall {:.0}{}), conn: {}, {}{}{}", qps, result.qps, target, conn, histogram, errors, warming));
                 }
-                std::io::stdout().flush().unwrap();
                 log_last_cnt = cnt;
                 log_instance = std::time::Instant::now();
             }
-            if !auto_connection.ready {
-                auto_connection.adjust(&histogram);
-                if auto_connection.ready {
-                    overall_cnt_overhead = histogram.cnt();
-                    overall_time = std::time::Instant::now();
-                    context.start_timer();
+            match signal() {
+                Signal::Interrupt if context.is_interrupted() => std::process::exit(130),
+                Signal::Interrupt => {
+                    context.interrupt();
+                    output.warn("interrupted, waiting for in-flight requests, press Ctrl-C again to exit");
                 }
+                Signal::Stop => context.stop(),
+                Signal::None => {}
+            }
+            let error_cnt = errors.cnt();
+            let error_rate = error_cnt as f64 / (error_cnt + histogram.cnt()) as f64;
+            if error_cnt > 0 && error_rate > case.max_error_rate && !context.is_stopped() {
+                output.warn(&format!("error rate {:.2}% exceeds {:.2}%, aborting", error_rate * 100.0, case.max_error_rate * 100.0));
+                context.stop();
             }
         }
         let conn: u64 = auto_connection.active_conn();
-        if context.is_loading {
-            println!("\x1B[F\x1B[2KData loaded, qps: {:.0}, time elapsed: {:.2}s\n", result.qps, overall_time.elapsed().as_secs_f64());
-        } else {
-            println!("\x1B[F\x1B[2Kqps: {:.0}, conn: {}, {}\n", result.qps, conn, histogram)
-        };
-        result.avg_latency_ms = histogram.avg() as f64 / 1_000.0;
-        result.p99_latency_ms = histogram.percentile(0.99) as f64 / 1_000.0;
+        if measuring {
+            // the last, partial interval
+            let interval_histogram = context.swap_interval_histogram();
+            let error_cnt = errors.cnt();
+            if interval_histogram.cnt() > 0 || error_cnt > sample_last_errors {
+                let sample = Sample::new(interval_histogram, sample_instant.elapsed().as_secs_f64(), error_cnt - sample_last_errors, conn);
+                output.sample(&sample);
+                result.samples.push(sample);
+            }
+        }
+        let overall_cnt = (histogram.cnt() - overall_cnt_overhead).max(1) as f64;
+        for (inx, command_histogram) in context.command_histograms.iter().enumerate() {
+            let mut command_result = CommandResult {
+                command: case.commands[inx].to_string(),
+                weight: case.weights[inx],
+                qps: result.qps * (command_histogram.cnt() - command_cnt_overhead[inx]) as f64 / overall_cnt,
+                avg_value_size: avg_value_size(&context, &value_overhead, inx..inx + 1),
+                ..Default::default()
+            };
+            command_result.set_latency(command_histogram);
+            result.commands.push(command_result);
+        }
+        result.set_latency(&histogram);
+        result.avg_value_size = avg_value_size(&context, &value_overhead, 0..case.commands.len());
         result.connections = conn;
+        result.rate = case.rate;
+        result.errors = errors.cnt();
+        result.error_kinds = errors.kinds().into_iter().collect();
+        result.interrupted = context.is_interrupted();
+        output.result(case, &result, &histogram, &context.command_histograms, context.is_loading, overall_time.elapsed());
     });
-    return result;
+    result
 }
 
-pub fn do_benchmark(client_config: ClientConfig, cores: Vec<u16>, case: Case, load: bool, quiet: bool) -> BenchmarkResult {
-    if !quiet {
-        println!("{}: {}", "command".bold().blue(), case.command.to_string().green().bold());
-        println!("{}: {}", "connections".bold().blue(), if case.connections == 0 { "auto".to_string() } else { case.connections.to_string() });
-        println!("{}: {}", "count".bold().blue(), case.count);
-        println!("{}: {}", "seconds".bold().blue(), case.seconds);
-        println!("{}: {}", "pipeline".bold().blue(), case.pipeline);
-    }
+// Generates pipelines of the commands on this thread without a server, as fast as it can, first through redis::Cmd
+// and a packed redis::Pipeline as for cluster connections, then encoded as for standalone connections. Returns the
+// requests per second of both, the client side cost of each request.
+pub fn self_benchmark(commands: &[Command], pipeline: u64, duration: std::time::Duration) -> (f64, f64) {
+    let mut cmds = commands.to_vec();
+    let mut rate = |encoded: bool| {
+        let instant = std::time::Instant::now();
+        let mut buf = Vec::new();
+        let mut cnt = 0;
+        while instant.elapsed() < duration {
+            if encoded {
+                buf.clear();
+                for inx in cnt..cnt + pipeline {
+                    cmds[inx as usize % commands.len()].encode(&mut buf);
+                }
+                std::hint::black_box(&buf);
+            } else {
+                let mut p = redis::pipe();
+                for inx in cnt..cnt + pipeline {
+                    p.add_command(cmds[inx as usize % commands.len()].gen_cmd()).ignore();
+                }
+                std::hint::black_box(p.get_packed_pipeline());
+            }
+            cnt += pipeline;
+        }
+        cnt as f64 / instant.elapsed().as_secs_f64()
+    };
+    (rate(false), rate(true))
+}
+
+// signal is polled by the log thread while the benchmark runs.
+pub fn do_benchmark(client_config: ClientConfig, cores: Vec<u16>, case: Case, load: bool, mut output: Output, signal: &dyn Fn() -> Signal) -> BenchmarkResult {
+    output.header(&case);
 
     // calc connections
     let auto_connection = AutoConnection::new(case.connections, cores.len() as u64);
@@ -144,7 +326,7 @@ pub fn do_benchmark(client_config: ClientConfig, cores: Vec<u16>, case: Case, lo
     let mut thread_handlers = Vec::new();
     let wg = WaitGroup::new();
     let core_ids = core_affinity::get_core_ids().unwrap();
-    let context = SharedContext::new(case.count, case.seconds, load);
+    let context = SharedContext::new(case.count, case.seconds, load, case.commands.len(), case.rate, case.poisson, case.histogram_digits, case.histogram_max_seconds * 1_000_000_000);
     for inx in 0..cores.len() {
         let client_config = client_config.clone();
         let case = case.clone();
@@ -152,11 +334,12 @@ pub fn do_benchmark(client_config: ClientConfig, cores: Vec<u16>, case: Case, lo
         let wk = wg.worker();
         let core_id = core_ids[cores[inx] as usize];
         let limiter = auto_connection.limiters[inx].clone();
+        let first_conn_id: u64 = auto_connection.limiters[..inx].iter().map(|limiter| limiter.total_conn).sum();
         let thread_handler = std::thread::spawn(move || {
             core_affinity::set_for_current(core_id); // not work on Apple Silicon
             let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
             rt.block_on(async {
-                run_commands_on_single_thread(limiter, client_config, case, context).await;
+                run_commands_on_single_thread(inx as u64, first_conn_id, limiter, client_config, case, context).await;
                 wk.done();
             });
         });
@@ -164,7 +347,7 @@ pub fn do_benchmark(client_config: ClientConfig, cores: Vec<u16>, case: Case, lo
     }
 
     // log thread
-    let result = wait_finish(&case, auto_connection, context, wg, quiet);
+    let result = wait_finish(&case, auto_connection, context, wg, &mut output, signal);
 
     // join all threads
     for thread_handler in thread_handlers {
@@ -177,5 +360,5 @@ pub fn do_benchmark(client_config: ClientConfig, cores: Vec<u16>, case: Case, lo
         }
     }
 
-    return result;
+    result
 }
\ No newline at end of file
@@ -1,99 +1,120 @@
-use enum_delegate;
-use redis::aio::ConnectionLike;
-use redis::aio::MultiplexedConnection;
+use futures_util::future::join_all;
 use redis::cluster_async::ClusterConnection;
-use redis::{Cmd, RedisFuture, Value};
+use redis::{RedisError, RedisResult};
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 use urlencoding::encode;
 
+use crate::command::Command;
+use crate::connection::{RespConnection, Stream};
+use crate::tls::TlsConfig;
+
 #[derive(Clone)]
 pub struct ClientConfig {
     pub cluster: bool,
     pub address: String,
     pub username: String,
     pub password: String,
-    pub tls: bool,
+    pub tls: Option<TlsConfig>,
     pub timeout: u64,
 }
 
 impl ClientConfig {
-    pub async fn get_client(&self) -> Client {
+    pub async fn get_client(&self) -> RedisResult<Client> {
+        Ok(Client::new(self.connect().await?))
+    }
+
+    async fn connect(&self) -> RedisResult<ClientConnection> {
         let username = encode(&self.username);
         let password = encode(&self.password);
-        let conn_str = if self.tls {
-            format!("rediss://{}:{}@{}/#insecure", username, password, &self.address)
-        } else {
-            format!("redis://{}:{}@{}", username, password, &self.address)
-        };
-
+        let timeout = Duration::from_secs(self.timeout);
         if self.cluster {
-            let nodes = vec![conn_str];
-            let client = redis::cluster::ClusterClient::builder(nodes).connection_timeout(std::time::Duration::from_secs(self.timeout)).build().unwrap();
-            let conn = match client.get_async_connection().await {
-                Ok(conn) => conn,
-                Err(e) => {
-                    eprintln!("Failed to connect to {}: {}", self.address, e);
-                    std::process::exit(1);
-                }
-            };
-            Client::new(conn.into())
-        } else {
-            let client = redis::Client::open(conn_str).unwrap();
-            let conn = match client.get_multiplexed_async_connection().await {
-                Ok(conn) => conn,
-                Err(e) => {
-                    eprintln!("Failed to connect to {}: {}", self.address, e);
-                    std::process::exit(1);
-                }
+            let conn_str = match &self.tls {
+                Some(tls) if tls.insecure => format!("rediss://{}:{}@{}/#insecure", username, password, &self.address),
+                Some(_) => format!("rediss://{}:{}@{}", username, password, &self.address),
+                None => format!("redis://{}:{}@{}", username, password, &self.address),
             };
-            Client::new(conn.into())
+            let mut builder = redis::cluster::ClusterClient::builder(vec![conn_str]).connection_timeout(timeout).response_timeout(timeout);
+            if let Some(tls) = &self.tls {
+                builder = builder.certs(tls.certificates());
+            }
+            let conn = builder.build()?.get_async_connection().await?;
+            return Ok(ClientConnection::Cluster(conn, Vec::new()));
         }
+
+        // TLS handshake is done here instead of by the redis crate, so that the SNI can differ from the address.
+        let host = self.address.rsplit_once(':').map_or(self.address.as_str(), |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let stream = tokio::time::timeout(timeout, async {
+            let stream = tokio::net::TcpStream::connect(&self.address).await?;
+            stream.set_nodelay(true)?;
+            let stream: Box<dyn Stream> = match &self.tls {
+                Some(tls) => Box::new(tls.connect(host, stream).await?),
+                None => Box::new(stream),
+            };
+            Ok::<_, std::io::Error>(stream)
+        });
+        let stream = stream.await.map_err(std::io::Error::from)??;
+        Ok(ClientConnection::Standalone(RespConnection::new(stream, &self.username, &self.password, timeout).await?, Vec::new()))
     }
 }
 
 impl Display for ClientConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "RedisConfig {{ cluster: {}, address: {}, username: {}, password: {}, tls: {} }}", self.cluster, self.address, self.username, self.password, self.tls)
+        write!(f, "RedisConfig {{ cluster: {}, address: {}, username: {}, password: {}, tls: {} }}", self.cluster, self.address, self.username, self.password, self.tls.is_some())
     }
 }
 
-#[enum_delegate::implement(ConnectionLike,
-trait ConnectionLike {
-fn req_packed_command < 'a > (& 'a mut self, cmd: & 'a Cmd) -> RedisFuture < 'a, Value >;
-fn req_packed_commands < 'a > (
-& 'a mut self,
-cmd: & 'a redis::Pipeline,
-offset: usize,
-count: usize,
-) -> RedisFuture < 'a, Vec < Value >>;
-fn get_db(& self) -> i64;
-}
-)]
+// Each connection with the pipeline prepared for it.
 enum ClientConnection {
-    Standalone(MultiplexedConnection),
-    Cluster(ClusterConnection),
+    Standalone(RespConnection, Vec<u8>),         // encoded by Command::encode
+    Cluster(ClusterConnection, Vec<redis::Cmd>), // a redis::Cmd per request, to route it to its node
 }
 
 pub struct Client {
     conn: ClientConnection,
+    pipeline_cnt: u64, // requests in the prepared pipeline
 }
 
 impl Client {
     fn new(conn: ClientConnection) -> Client {
-        Client { conn }
+        Client { conn, pipeline_cnt: 0 }
     }
 
-
-    pub async fn run_commands(&mut self, cmds: Vec<redis::Cmd>) {
-        let mut pipeline = redis::pipe();
-        for cmd in cmds {
-            pipeline.add_command(cmd).ignore();
+    /// Generates the next pipeline from the commands at inxs, in the form the connection sends. Replaces a pipeline
+    /// prepared before and not run.
+    pub fn prepare(&mut self, cmds: &mut [Command], inxs: &[usize]) {
+        match &mut self.conn {
+            ClientConnection::Standalone(_, buf) => {
+                buf.clear();
+                inxs.iter().for_each(|&inx| cmds[inx].encode(buf));
+            }
+            ClientConnection::Cluster(_, pipeline) => *pipeline = inxs.iter().map(|&inx| cmds[inx].gen_cmd()).collect(),
         }
-        match pipeline.query_async(&mut self.conn).await {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!("Failed to execute pipeline: {:?}", e);
-                std::process::exit(1);
+        self.pipeline_cnt = inxs.len() as u64;
+    }
+
+    /// Runs the prepared pipeline and returns the error replies of its requests by index. A pipeline fails as a whole
+    /// when the connection does or the replies time out.
+    pub async fn run(&mut self) -> RedisResult<Vec<(usize, RedisError)>> {
+        match &mut self.conn {
+            ClientConnection::Standalone(conn, buf) => conn.run(buf, self.pipeline_cnt).await,
+            // Sent together, each to its node. A redis::Pipeline would go to a single node and only return its first error.
+            ClientConnection::Cluster(conn, cmds) => {
+                let replies = join_all(cmds.iter().map(|cmd| {
+                    let mut conn = conn.clone();
+                    async move { cmd.query_async::<redis::Value>(&mut conn).await }
+                }))
+                .await;
+                let mut errors = Vec::new();
+                for (i, reply) in replies.into_iter().enumerate() {
+                    match reply {
+                        Ok(_) => {}
+                        Err(e) if e.code().is_some() => errors.push((i, e)),
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(errors)
             }
         }
     }
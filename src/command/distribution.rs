@@ -1,44 +1,324 @@
 use std::sync::Arc;
 use std::sync::atomic::AtomicU64;
 use rand::distributions::Distribution;
+use rand_distr::{Exp, Normal};
+use crate::command::parser::Invalid;
+
+// Options of the distributions, given as `name=value` after the range. The others belong to the placeholder.
+pub const OPTIONS: [&str; 6] = ["exponent", "mean", "stddev", "hot_keys", "hot_ops", "shard"];
+
+// Samples of gaussian and exponential outside of the range are drawn again, so at least this share of them has to
+// fall inside, and after MAX_REDRAWS misses in a row the last one is clamped to the range.
+const MIN_MASS_IN_RANGE: f64 = 0.1;
+const MAX_REDRAWS: usize = 100;
+
+// "I/N", the I-th of N parts, counted from 0.
+pub fn parse_shard(s: &str) -> Result<(u64, u64), Invalid> {
+    let invalid = || Invalid::expected(format!("invalid shard: {}", s), "I/N with I < N");
+    let (i, n) = s.split_once('/').ok_or_else(invalid)?;
+    let (i, n) = (i.parse::<u64>().map_err(|_| invalid())?, n.parse::<u64>().map_err(|_| invalid())?);
+    if i >= n {
+        return Err(invalid());
+    }
+    Ok((i, n))
+}
+
+// The values of the I-th of N contiguous parts of 0..range.
+pub fn shard_range(range: u64, (i, n): (u64, u64)) -> std::ops::Range<u64> {
+    let bound = |i: u64| (range as u128 * i as u128 / n as u128) as u64;
+    bound(i)..bound(i + 1)
+}
 
 #[derive(Clone, Debug)]
 pub enum DistributionEnum {
     Uniform(rand::distributions::Uniform<u64>),
     Zipfian(zipf::ZipfDistribution),
     Sequence(SequenceDistribution),
+    Gaussian(GaussianDistribution),
+    Hotspot(HotspotDistribution),
+    Latest(LatestDistribution),
+    Exponential(ExponentialDistribution),
 }
 
 impl DistributionEnum {
-    pub fn new(s: &str, range: u64) -> Self {
-        match s {
-            "uniform" => Self::Uniform(rand::distributions::Uniform::new(0, range)),
-            "zipfian" => Self::Zipfian(zipf::ZipfDistribution::new(range as usize, 1.03).unwrap()),
-            "sequence" => Self::Sequence(SequenceDistribution::new(range)),
-            _ => panic!("Unknown distribution"),
+    pub fn new(s: &str, range: u64, options: &[&str]) -> Result<Self, Invalid> {
+        if range == 0 {
+            return Err("N must be greater than 0".into());
         }
+        let mut exponent = 1.03;
+        let mut mean = None;
+        let mut stddev = range as f64 / 6.0;
+        let mut hot_keys = 0.2;
+        let mut hot_ops = 0.8;
+        let mut shard = None;
+        for option in options {
+            let (name, value) = option.split_once('=').ok_or_else(|| format!("option {} needs a value", option))?;
+            if name == "shard" {
+                if s != "sequence" {
+                    return Err("shard is only supported by sequence".into());
+                }
+                shard = Some(parse_shard(value)?);
+                continue;
+            }
+            let value: f64 = value.parse().map_err(|_| format!("invalid {}: {}", name, value))?;
+            match name {
+                "exponent" => exponent = value,
+                "mean" => mean = Some(value),
+                "stddev" => stddev = value,
+                "hot_keys" => hot_keys = value,
+                "hot_ops" => hot_ops = value,
+                _ => return Err(format!("unknown option: {}", name).into()),
+            }
+        }
+        if mean.is_some_and(|mean| !mean.is_finite()) {
+            return Err(format!("mean must be finite, got {}", mean.unwrap()).into());
+        }
+        let fraction = |name: &str, value: f64| if (0.0..=1.0).contains(&value) { Ok(value) } else { Err(format!("{} must be between 0 and 1", name)) };
+        let zipf = |exponent: f64| zipf::ZipfDistribution::new(range as usize, exponent).map_err(|_| format!("exponent must be greater than 0, got {}", exponent));
+        let distribution = match s {
+            "uniform" => Self::Uniform(rand::distributions::Uniform::new(0, range)),
+            "zipfian" => Self::Zipfian(zipf(exponent)?),
+            "sequence" => {
+                let values = shard_range(range, shard.unwrap_or((0, 1)));
+                if values.is_empty() {
+                    return Err(format!("more shards than the {} values", range).into());
+                }
+                Self::Sequence(SequenceDistribution::new(values))
+            }
+            "gaussian" => {
+                let mean = mean.unwrap_or(range as f64 / 2.0);
+                let normal = Normal::new(mean, stddev).map_err(|_| format!("stddev must not be negative, got {}", stddev))?;
+                let mass = if stddev > 0.0 {
+                    normal_cdf((range as f64 - mean) / stddev) - normal_cdf(-mean / stddev)
+                } else if mean >= 0.0 && mean < range as f64 {
+                    1.0
+                } else {
+                    0.0
+                };
+                if mass < MIN_MASS_IN_RANGE {
+                    return Err(format!("mean={} stddev={} leaves less than 10% of the samples in 0..{}", mean, stddev, range).into());
+                }
+                Self::Gaussian(GaussianDistribution { normal, range })
+            }
+            "hotspot" => {
+                let hot_range = ((range as f64 * fraction("hot_keys", hot_keys)?) as u64).clamp(1, range);
+                Self::Hotspot(HotspotDistribution { hot_range, range, hot_ops: fraction("hot_ops", hot_ops)? })
+            }
+            "latest" => Self::Latest(LatestDistribution { zipf: zipf(exponent)?, range }),
+            "exponential" => {
+                let mean = mean.unwrap_or(range as f64 / 10.0);
+                if mean <= 0.0 {
+                    return Err(format!("mean must be greater than 0, got {}", mean).into());
+                }
+                let exp = Exp::new(1.0 / mean).map_err(|_| format!("mean must be greater than 0, got {}", mean))?;
+                if -(-(range as f64) / mean).exp_m1() < MIN_MASS_IN_RANGE {
+                    return Err(format!("mean={} leaves less than 10% of the samples in 0..{}", mean, range).into());
+                }
+                Self::Exponential(ExponentialDistribution { exp, range })
+            }
+            _ => return Err(Invalid::expected(format!("unknown distribution: {}", s), "uniform, zipfian, sequence, gaussian, hotspot, latest or exponential")),
+        };
+        Ok(distribution)
     }
     pub fn sample(&mut self, rng: &mut impl rand::Rng) -> u64 {
         match self {
             Self::Uniform(d) => d.sample(rng),
-            Self::Zipfian(d) => d.sample(rng) as u64,
+            Self::Zipfian(d) => d.sample(rng) as u64 - 1, // zipf ranks start from 1
             Self::Sequence(d) => d.sample(),
+            Self::Gaussian(d) => d.sample(rng),
+            Self::Hotspot(d) => d.sample(rng),
+            Self::Latest(d) => d.range - d.zipf.sample(rng) as u64,
+            Self::Exponential(d) => d.sample(rng),
+        }
+    }
+    pub fn is_sequence(&self) -> bool {
+        matches!(self, Self::Sequence(_))
+    }
+    // The next n samples of a sequence are start..start + n, reserved by the caller.
+    pub fn reserve(&mut self, start: u64, n: u64) {
+        if let Self::Sequence(d) = self {
+            d.next = start;
+            d.end = start + n;
         }
     }
 }
 
+// Walks start..start + range, of all values or of a shard. The counter is shared by all copies, each value is handed
+// out once. Values reserved for a copy, see Command::reserve, are taken from [next, end) instead.
 #[derive(Clone, Debug)]
 pub struct SequenceDistribution {
+    start: u64,
     range: u64,
     current: Arc<AtomicU64>,
+    next: u64,
+    end: u64,
 }
 
 impl SequenceDistribution {
-    fn new(range: u64) -> Self {
-        Self { range, current: Arc::new(AtomicU64::new(0)) }
+    fn new(values: std::ops::Range<u64>) -> Self {
+        Self { start: values.start, range: values.end - values.start, current: Arc::new(AtomicU64::new(0)), next: 0, end: 0 }
     }
     fn sample(&mut self) -> u64 {
-        let ret = self.current.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        ret % self.range
+        let ret = if self.next < self.end {
+            self.next += 1;
+            self.next - 1
+        } else {
+            self.current.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        };
+        self.start + ret % self.range
+    }
+}
+
+// The standard normal CDF, from the erfc approximation 7.1.26 of Abramowitz and Stegun, good to about 1e-7.
+fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * z);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erfc = poly * (-z * z).exp();
+    if x >= 0.0 {
+        1.0 - erfc / 2.0
+    } else {
+        erfc / 2.0
+    }
+}
+
+// Samples outside of [0, range) are drawn again, at most MAX_REDRAWS times.
+#[derive(Clone, Debug)]
+pub struct GaussianDistribution {
+    normal: Normal<f64>,
+    range: u64,
+}
+
+impl GaussianDistribution {
+    fn sample(&self, rng: &mut impl rand::Rng) -> u64 {
+        let mut x = 0.0;
+        for _ in 0..MAX_REDRAWS {
+            x = self.normal.sample(rng);
+            if x >= 0.0 && x < self.range as f64 {
+                break;
+            }
+        }
+        (x as u64).min(self.range - 1) // the cast saturates negative values to 0
+    }
+}
+
+// hot_ops of the requests go to the first hot_range keys, the rest to the other keys.
+#[derive(Clone, Debug)]
+pub struct HotspotDistribution {
+    hot_range: u64,
+    range: u64,
+    hot_ops: f64,
+}
+
+impl HotspotDistribution {
+    fn sample(&self, rng: &mut impl rand::Rng) -> u64 {
+        if self.hot_range == self.range || rng.gen_bool(self.hot_ops) {
+            rng.gen_range(0..self.hot_range)
+        } else {
+            rng.gen_range(self.hot_range..self.range)
+        }
+    }
+}
+
+// Zipfian from the end of the range, so the most recently loaded keys of a sequence are the hottest.
+#[derive(Clone, Debug)]
+pub struct LatestDistribution {
+    zipf: zipf::ZipfDistribution,
+    range: u64,
+}
+
+// Samples outside of [0, range) are drawn again, at most MAX_REDRAWS times.
+#[derive(Clone, Debug)]
+pub struct ExponentialDistribution {
+    exp: Exp<f64>,
+    range: u64,
+}
+
+impl ExponentialDistribution {
+    fn sample(&self, rng: &mut impl rand::Rng) -> u64 {
+        let mut x = 0.0;
+        for _ in 0..MAX_REDRAWS {
+            x = self.exp.sample(rng);
+            if x < self.range as f64 {
+                break;
+            }
+        }
+        (x as u64).min(self.range - 1)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DistributionEnum;
+
+    // counts of samples in each tenth of the range
+    fn deciles(s: &str, options: &[&str]) -> Vec<u64> {
+        let mut rng = rand::thread_rng();
+        let mut d = DistributionEnum::new(s, 1000, options).unwrap();
+        let mut deciles = vec![0; 10];
+        for _ in 0..100_000 {
+            let x = d.sample(&mut rng);
+            assert!(x < 1000, "{} out of range: {}", s, x);
+            deciles[x as usize / 100] += 1;
+        }
+        deciles
+    }
+
+    #[test]
+    fn test_distributions() {
+        let uniform = deciles("uniform", &[]);
+        assert!(uniform.iter().all(|&n| n > 9_000 && n < 11_000), "{:?}", uniform);
+
+        let zipfian = deciles("zipfian", &[]);
+        assert!(zipfian[0] > zipfian[9] * 5, "{:?}", zipfian);
+        let flat = deciles("zipfian", &["exponent=0.1"]);
+        assert!(flat[0] < flat[9] * 2, "{:?}", flat);
+
+        let latest = deciles("latest", &[]);
+        assert!(latest[9] > latest[0] * 5, "{:?}", latest);
+
+        let gaussian = deciles("gaussian", &["mean=250", "stddev=50"]);
+        assert!(gaussian[2] > 30_000 && gaussian[9] == 0, "{:?}", gaussian);
+
+        // 90% of requests on the first 10% of keys
+        let hotspot = deciles("hotspot", &["hot_keys=0.1", "hot_ops=0.9"]);
+        assert!(hotspot[0] > 88_000 && hotspot[0] < 92_000, "{:?}", hotspot);
+
+        let exponential = deciles("exponential", &["mean=100"]);
+        assert!(exponential[0] > exponential[1] && exponential[1] > exponential[2], "{:?}", exponential);
+
+        let mut sequence = DistributionEnum::new("sequence", 3, &[]).unwrap();
+        let mut rng = rand::thread_rng();
+        assert_eq!((0..4).map(|_| sequence.sample(&mut rng)).collect::<Vec<_>>(), vec![0, 1, 2, 0]);
+
+        // a reserved block is used before the shared counter
+        let mut sequence = DistributionEnum::new("sequence", 10, &[]).unwrap();
+        sequence.reserve(4, 2);
+        assert_eq!((0..4).map(|_| sequence.sample(&mut rng)).collect::<Vec<_>>(), vec![4, 5, 0, 1]);
+
+        // 10 values in 3 shards: 0..3, 3..6, 6..10
+        let mut sequence = DistributionEnum::new("sequence", 10, &["shard=2/3"]).unwrap();
+        assert_eq!((0..5).map(|_| sequence.sample(&mut rng)).collect::<Vec<_>>(), vec![6, 7, 8, 9, 6]);
+        assert_eq!(super::parse_shard("1/4"), Ok((1, 4)));
+        assert!(super::parse_shard("4/4").is_err());
+        assert!(super::parse_shard("1").is_err());
+        assert!(DistributionEnum::new("sequence", 2, &["shard=0/3"]).is_err());
+        assert!(DistributionEnum::new("uniform", 10, &["shard=0/2"]).is_err());
+
+        assert!(DistributionEnum::new("pareto", 1000, &[]).is_err());
+        assert!(DistributionEnum::new("zipfian", 1000, &["exponent=0"]).is_err());
+        assert!(DistributionEnum::new("hotspot", 1000, &["hot_ops=80"]).is_err());
+        assert!(DistributionEnum::new("gaussian", 1000, &["stddev"]).is_err());
+        // most samples outside of the range would be drawn again and again
+        assert!(DistributionEnum::new("gaussian", 1000, &["mean=1e12"]).is_err());
+        assert!(DistributionEnum::new("gaussian", 1000, &["mean=-1e9", "stddev=1"]).is_err());
+        assert!(DistributionEnum::new("gaussian", 1000, &["mean=2000", "stddev=0"]).is_err());
+        assert!(DistributionEnum::new("gaussian", 1000, &["mean=nan"]).is_err());
+        assert!(DistributionEnum::new("gaussian", 1000, &["stddev=nan"]).is_err());
+        assert!(DistributionEnum::new("exponential", 10, &["mean=1e300"]).is_err());
+        assert!(DistributionEnum::new("exponential", 10, &["mean=nan"]).is_err());
+        assert_eq!(deciles("gaussian", &["mean=0", "stddev=1"])[0], 100_000);
+        assert_eq!(deciles("exponential", &["mean=1e-300"])[0], 100_000);
+        assert!(DistributionEnum::new("uniform", 0, &[]).is_err());
+    }
+}
@@ -1,65 +1,290 @@
-use crate::command::placeholder::PlaceholderEnum;
-use redis;
-use std::sync::{Arc, Mutex};
+use crate::command::placeholder::{encode_args, fixed_argc, gen_args, GenContext, PlaceholderEnum};
+use std::collections::HashMap;
+use crate::rng::FastRng;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 mod distribution;
 mod parser;
 mod placeholder;
 
+pub use distribution::parse_shard;
+pub use parser::ParseError;
+pub use placeholder::write_bulk;
+
 #[derive(Clone, Debug)]
 pub struct Command {
     str: String,
-    argv: Vec<PlaceholderEnum>,
-    #[allow(dead_code)]
-    lock: Arc<Mutex<()>>,
+    argv: Vec<Vec<PlaceholderEnum>>, // the parts of each argument, concatenated when generated
+    argc: Option<u64>,               // the number of arguments, unless it varies with {repeat MIN-MAX}
+    // generated by this copy of the command
+    gen_cnt: u64,
+    value_bytes: u64,
+    conn_id: u64,
+    thread_id: u64,
+    rng: FastRng,
+    generations: Arc<AtomicU64>, // reserved by all copies
+    encoded_args: Vec<u8>,       // reused by encode when argc varies
 }
 
 impl Command {
-    pub fn new(cmd: &str) -> Command {
-        let prev_cmd = cmd;
-        match parser::parse_all(cmd) {
-            Ok((nm, args)) => {
-                assert_eq!(nm, "");
-                Command {
-                    str: prev_cmd.to_string(),
-                    argv: args,
-                    lock: Arc::new(Mutex::new(())),
-                }
-            }
-            Err(e) => {
-                panic!("cmd parse error. cmd: {}, error: {:?}", cmd, e);
-            }
-        }
+    pub fn new(cmd: &str) -> Result<Command, ParseError> {
+        let argv = parser::parse_all(cmd)?;
+        Ok(Command {
+            str: cmd.to_string(),
+            argc: fixed_argc(&argv, &mut HashMap::new()),
+            argv,
+            gen_cnt: 0,
+            value_bytes: 0,
+            conn_id: 0,
+            thread_id: 0,
+            rng: FastRng::from_entropy(),
+            generations: Arc::new(AtomicU64::new(0)),
+            encoded_args: Vec::new(),
+        })
     }
     pub fn gen_cmd(&mut self) -> redis::Cmd {
         let mut cmd = redis::Cmd::new();
-        let mut cmd_str = String::new();
-        for ph in self.argv.iter_mut() {
-            for arg in ph.gen() {
-                cmd_str.push_str(&arg);
+        let mut context = GenContext::new(&mut self.rng, self.conn_id, self.thread_id);
+        let mut args = Vec::new();
+        gen_args(&mut self.argv, &mut context, &mut args);
+        for arg in args {
+            cmd.arg(arg);
+        }
+        self.value_bytes += context.value_bytes;
+        self.gen_cnt += 1;
+        cmd
+    }
+    // Appends the command to buf as RESP, the bytes gen_cmd would send without building a redis::Cmd.
+    pub fn encode(&mut self, buf: &mut Vec<u8>) {
+        let mut context = GenContext::new(&mut self.rng, self.conn_id, self.thread_id);
+        match self.argc {
+            Some(argc) => {
+                write!(buf, "*{}\r\n", argc).unwrap();
+                let encoded = encode_args(&mut self.argv, &mut context, buf);
+                debug_assert_eq!(encoded, argc);
+            }
+            // the arguments go before their number, which is only known once they are generated
+            None => {
+                self.encoded_args.clear();
+                let argc = encode_args(&mut self.argv, &mut context, &mut self.encoded_args);
+                write!(buf, "*{}\r\n", argc).unwrap();
+                buf.extend_from_slice(&self.encoded_args);
             }
         }
-        for word in cmd_str.split_whitespace() {
-            cmd.arg(word);
+        self.value_bytes += context.value_bytes;
+        self.gen_cnt += 1;
+    }
+    // Binds this copy to a connection: the ids for {conn_id} and {thread_id}, and the seed of its random stream.
+    pub fn set_connection(&mut self, conn_id: u64, thread_id: u64, seed: u64) {
+        self.conn_id = conn_id;
+        self.thread_id = thread_id;
+        self.rng = FastRng::new(seed);
+    }
+    // Reserves the next n calls of gen_cmd on this copy with a single atomic add. The i-th generation of the command
+    // gets the i-th values of all its sequences, so sequences of one command stay in step, e.g. every member of
+    // SADD {key sequence 1000} {key sequence 10007} is added once, without locking across connections.
+    pub fn reserve(&mut self, n: u64) {
+        let start = self.generations.fetch_add(n, Ordering::Relaxed);
+        self.argv.iter_mut().flatten().for_each(|p| p.reserve(start, n));
+    }
+    // Makes this run the I-th of N that together generate the command count times, returns its part of count. The
+    // generations are counted from where the runs before it stop, so the sequences take the values they would take
+    // in a single run. Sequences inside a {repeat} with a count range can not be split, every run would walk them
+    // from the start.
+    pub fn set_shard(&mut self, shard: (u64, u64), count: u64) -> Result<u64, String> {
+        if self.argv.iter().flatten().any(|p| p.has_unreserved_sequence()) {
+            return Err("shard does not support sequences inside a {repeat} with a count range".to_string());
         }
-        cmd
+        let generations = distribution::shard_range(count, shard);
+        self.generations.store(generations.start, Ordering::Relaxed);
+        Ok(generations.end - generations.start)
     }
-    #[allow(dead_code)]
-    pub fn gen_cmd_with_lock(&mut self) -> redis::Cmd {
-        let _lock = self.lock.lock().unwrap();
-        let mut cmd = redis::Cmd::new();
-        let mut cmd_str = String::new();
-        for ph in self.argv.iter_mut() {
-            for arg in ph.gen() {
-                cmd_str.push_str(&arg);
+    pub fn gen_cnt(&self) -> u64 {
+        self.gen_cnt
+    }
+    // bytes of the {value} placeholders
+    pub fn value_bytes(&self) -> u64 {
+        self.value_bytes
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use redis::Arg;
+
+    fn args(cmd: &redis::Cmd) -> Vec<Vec<u8>> {
+        cmd.args_iter()
+            .map(|arg| match arg {
+                Arg::Simple(bytes) => bytes.to_vec(),
+                Arg::Cursor => panic!("unexpected cursor"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gen_cmd() {
+        let mut command = Command::new("SET \"key with space\" {value 8} {range 10 0}").unwrap();
+        let argv = args(&command.gen_cmd());
+        assert_eq!(argv.len(), 5);
+        assert_eq!(argv[1], b"key with space");
+        assert_eq!(argv[2].len(), 8);
+        assert_eq!(argv[3], argv[4]);
+
+        let mut command = Command::new("MSET a {value 1:1,3:1} b x{value 10}").unwrap();
+        for _ in 0..100 {
+            command.gen_cmd();
+        }
+        assert_eq!(command.gen_cnt(), 100);
+        assert!(command.value_bytes() > 1_100 && command.value_bytes() < 1_300);
+    }
+
+    #[test]
+    fn test_encode() {
+        // the same bytes as the redis::Cmd of the same random stream, with a number of arguments that varies and one
+        // that is known up front
+        for template in [
+            "HSET user:{key uniform 100} \"a b\" {value 4-8 content=derived} {repeat 1-3 {rand 10} x{rand 10}y} {range 10 2}",
+            "EVAL {key:k uniform 100 hashtag} {range:r 10 2} {ref r} {ref k}:{uuid} {repeat 2 {value 0-9 content=compressible} {value 8 content=bytes}} {value 1-3 content=numeric} {float 0 1}{choice a b} {conn_id} {value 3 content=fixed}",
+        ] {
+            let template = Command::new(template).unwrap();
+            let (mut a, mut b) = (template.clone(), template);
+            a.set_connection(0, 0, 1);
+            b.set_connection(0, 0, 1);
+            let mut buf = Vec::new();
+            for _ in 0..10 {
+                buf.clear();
+                a.encode(&mut buf);
+                assert_eq!(buf, b.gen_cmd().get_packed_command());
             }
+            assert_eq!((a.gen_cnt(), a.value_bytes()), (b.gen_cnt(), b.value_bytes()));
+        }
+        assert_eq!(Command::new("SET {repeat 1-2 a} {range 10 2}").unwrap().argc, None);
+        assert_eq!(Command::new("SET {range:r 10 2} {repeat 2 a {ref r}}").unwrap().argc, Some(9));
+    }
+
+    #[test]
+    fn test_derived_value() {
+        let mut command = Command::new("HSET user:{key uniform 3 width=1} name {value 8-64 content=derived}").unwrap();
+        let mut values = std::collections::HashMap::new();
+        for _ in 0..100 {
+            let argv = args(&command.gen_cmd());
+            assert_eq!(values.entry(argv[1].clone()).or_insert(argv[3].clone()), &argv[3]);
         }
-        for word in cmd_str.split_whitespace() {
-            cmd.arg(word);
+        assert_eq!(values.len(), 3);
+    }
+
+    #[test]
+    fn test_named() {
+        let mut command = Command::new("EVAL {value:script 8} 2 {key:k1 uniform 100} {key:k2 uniform 100} k:{ref k1}:{ref k2}").unwrap();
+        for _ in 0..10 {
+            let argv = args(&command.gen_cmd());
+            assert_eq!(argv[5], [b"k:".to_vec(), argv[3].clone(), b":".to_vec(), argv[4].clone()].concat());
         }
-        cmd
+        let mut command = Command::new("ZRANGE {key:k sequence 10} {range:r 10 3} {ref r} {ref k}").unwrap();
+        let argv = args(&command.gen_cmd());
+        assert_eq!(argv[2..4], argv[4..6]);
+        assert_eq!(argv[1], argv[6]);
     }
-    pub fn to_string(&self) -> String {
-        self.str.clone()
+
+    #[test]
+    fn test_repeat() {
+        let mut command = Command::new("MSET {repeat 3 {key uniform 100} {value 8 content=derived}}").unwrap();
+        let argv = args(&command.gen_cmd());
+        assert_eq!(argv.len(), 7);
+        assert!(argv[1..].chunks(2).all(|kv| kv[0].starts_with(b"key_") && kv[1].len() == 8));
+        assert_eq!(command.value_bytes(), 24);
+
+        let mut command = Command::new("HSET h {repeat 1-4 {key:f sequence 100} {ref f}}").unwrap();
+        let mut lens = std::collections::BTreeSet::new();
+        for _ in 0..100 {
+            let argv = args(&command.gen_cmd());
+            assert!(argv[2..].chunks(2).all(|kv| kv[0] == kv[1]));
+            lens.insert(argv.len());
+        }
+        assert_eq!(lens.into_iter().collect::<Vec<_>>(), vec![4, 6, 8, 10]);
+    }
+
+    #[test]
+    fn test_seed() {
+        let template = Command::new("HSET {key zipfian 1000} {rand 100} {value 4-16} {choice a b c} {uuid} {repeat 1-3 {float 0 1}}").unwrap();
+        let gen = |seed: u64| {
+            let mut command = template.clone();
+            command.set_connection(0, 0, seed);
+            (0..20).map(|_| args(&command.gen_cmd())).collect::<Vec<_>>()
+        };
+        assert_eq!(gen(1), gen(1));
+        assert_ne!(gen(1), gen(2));
+    }
+
+    #[test]
+    fn test_reserve() {
+        // 4 threads generating 1999 commands each in pipelines of 1 to 3, each generation i gets a2i, a2i+1 and bi
+        let template = Command::new("MSET {repeat 2 {key sequence 100000 prefix=a width=0} 1} {key sequence 100000 prefix=b width=0} 1").unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mut command = template.clone();
+                std::thread::spawn(move || {
+                    let mut generations = Vec::new();
+                    for n in (0..1000).map(|i| i % 3 + 1) {
+                        command.reserve(n);
+                        for _ in 0..n {
+                            let argv = args(&command.gen_cmd());
+                            let id = |arg: &[u8]| std::str::from_utf8(&arg[1..]).unwrap().parse::<u64>().unwrap();
+                            let i = id(&argv[5]);
+                            assert_eq!((id(&argv[1]), id(&argv[3])), (2 * i, 2 * i + 1));
+                            generations.push(i);
+                        }
+                    }
+                    generations
+                })
+            })
+            .collect();
+        let mut generations: Vec<_> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+        generations.sort();
+        assert_eq!(generations, (0..4 * 1999).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_shard() {
+        // 3 runs loading 10 pairs of SADD {key sequence 2} {key sequence 5} together, each pair once
+        let mut pairs = Vec::new();
+        for i in 0..3 {
+            let mut command = Command::new("SADD {key sequence 2 width=0} {key sequence 5 width=0}").unwrap();
+            let count = command.set_shard((i, 3), 10).unwrap();
+            command.reserve(count);
+            pairs.extend((0..count).map(|_| args(&command.gen_cmd())[1..].to_vec()));
+        }
+        pairs.sort();
+        pairs.dedup();
+        assert_eq!(pairs.len(), 10);
+
+        assert!(Command::new("SADD s {repeat 1-3 {key:k sequence 10}}").unwrap().set_shard((0, 2), 10).is_err());
+        assert!(Command::new("SADD s {repeat 2 {repeat 1-3 x{rand 5}} {key sequence 10}}").unwrap().set_shard((0, 2), 10).is_ok());
+        assert!(Command::new("SADD s {repeat 2 {repeat 1-3 {value 1-5 sequence} } }").unwrap().set_shard((0, 2), 10).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Command::new("SET {ref k} {key:k uniform 10}").is_err());
+        assert!(Command::new("ZRANGE k {range:r 10 3} x{ref r}").is_err());
+        assert!(Command::new("SET k {value 8 content=derived}").is_err());
+        assert!(Command::new("SET k {value 0-18446744073709551615}").is_err());
+        assert!(Command::new("MSET {repeat 0-18446744073709551615 k v}").is_err());
+        assert!(Command::new("SET k {value 1-18446744073709551615}").is_err());
+        assert!(Command::new("SET k {value 64:1,536870913:1}").is_err());
+        assert!(Command::new("SET k {value 536870913}").is_err());
+        assert!(Command::new("SET k {value 1-536870912 zipfian}").is_ok());
+        let e = Command::new("ZRANGE k x{range 10 3}").unwrap_err();
+        assert_eq!(e.offset, 10);
     }
 }
@@ -1,46 +1,384 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use nom::{
     IResult,
-    sequence::delimited,
+    sequence::{delimited, terminated, tuple},
     branch::alt,
     bytes::complete::{is_not, tag},
-    multi::many0,
+    character::complete::{multispace0, multispace1},
+    multi::{many1, separated_list0, separated_list1},
     combinator::{map, all_consuming},
 };
 use crate::command::placeholder::PlaceholderEnum;
 
-fn parse_string(input: &str) -> IResult<&str, PlaceholderEnum> {
-    let s = alt((
-        delimited(tag("\""), is_not("\""), tag("\"")),
-        delimited(tag("\'"), is_not("\'"), tag("\'")),
-        is_not("{")
-    ));
-    map(s, PlaceholderEnum::new_string)(input)
+// An error in a command, offset is the byte offset in the command where it was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub command: String,
+    pub offset: usize,
+    pub message: String,          // what is wrong
+    pub expected: Option<String>, // what was expected at the offset, if known
 }
 
+impl ParseError {
+    fn new(command: &str, offset: usize, invalid: Invalid) -> Self {
+        ParseError { command: command.to_string(), offset, message: invalid.message, expected: invalid.expected }
+    }
+}
+
+// What is wrong with a part of a command, and what was expected instead if known. A plain message converts from String.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invalid {
+    pub message: String,
+    pub expected: Option<String>,
+}
+
+impl Invalid {
+    pub fn expected(message: impl Into<String>, expected: impl Into<String>) -> Self {
+        Invalid { message: message.into(), expected: Some(expected.into()) }
+    }
+}
+
+impl From<String> for Invalid {
+    fn from(message: String) -> Self {
+        Invalid { message, expected: None }
+    }
+}
+
+impl From<&str> for Invalid {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl Display for Invalid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(expected) = &self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ParseError {
+    // The message, then the command with a caret under the offset.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let column = self.command[..self.offset].chars().count();
+        write!(f, "{}", self.message)?;
+        if let Some(expected) = &self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        write!(f, " at offset {}\n  {}\n  {}^", self.offset, self.command, " ".repeat(column))
+    }
+}
+
+// A part of an argument as written in the command. The slices point into the command, so errors can tell their offset.
+enum Part<'a> {
+    String(&'a str, Vec<u8>),                     // as written and with the escapes replaced
+    Placeholder(&'a str),                         // the text inside the braces
+    Repeat(&'a str, &'a str, Vec<Vec<Part<'a>>>), // the opening brace, the count and the arguments of the group
+}
+
+// Quoted strings keep spaces and braces and may be empty. The escapes are those of redis-cli: "..." takes \" \\ \n \r \t \b \a
+// and \xHH, any other escaped character stands for itself, while '...' only takes \'.
+fn parse_quoted(input: &str) -> IResult<&str, Part<'_>> {
+    let bytes = input.as_bytes();
+    let quote = match bytes.first() {
+        Some(&quote) if quote == b'"' || quote == b'\'' => quote,
+        _ => return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Char))),
+    };
+    let mut value = Vec::new();
+    let mut i = 1;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == quote {
+            return Ok((&input[i + 1..], Part::String(&input[..i + 1], value)));
+        }
+        if c != b'\\' || i + 1 == bytes.len() {
+            value.push(c);
+            i += 1;
+            continue;
+        }
+        let escaped = bytes[i + 1];
+        let hex = |i: usize| bytes.get(i).filter(|c| c.is_ascii_hexdigit()).is_some();
+        match escaped {
+            b'\'' if quote == b'\'' => value.push(b'\''),
+            _ if quote == b'\'' => {
+                value.push(b'\\');
+                i += 1;
+                continue;
+            }
+            b'x' if hex(i + 2) && hex(i + 3) => {
+                value.push(u8::from_str_radix(&input[i + 2..i + 4], 16).unwrap());
+                i += 2;
+            }
+            b'n' => value.push(b'\n'),
+            b'r' => value.push(b'\r'),
+            b't' => value.push(b'\t'),
+            b'b' => value.push(0x08),
+            b'a' => value.push(0x07),
+            c => value.push(c),
+        }
+        i += 2;
+    }
+    Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Char)))
+}
+
+fn string(s: &str) -> Part<'_> {
+    Part::String(s, s.as_bytes().to_vec())
+}
+
+// {{ and }} stand for literal braces outside of quotes.
+fn parse_brace(input: &str) -> IResult<&str, Part<'_>> {
+    map(alt((tag("{{"), tag("}}"))), |s: &str| Part::String(s, s.as_bytes()[..1].to_vec()))(input)
+}
+
+// A single } is taken as is, in a {repeat} group it ends the group.
+fn parse_string(input: &str) -> IResult<&str, Part<'_>> {
+    map(alt((is_not(" \t\r\n{}\"\'"), tag("}"))), string)(input)
+}
+
+fn parse_group_string(input: &str) -> IResult<&str, Part<'_>> {
+    map(is_not(" \t\r\n{}\"\'"), string)(input)
+}
+
+fn parse_placeholder(input: &str) -> IResult<&str, Part<'_>> {
+    let inner = delimited(tag("{"), is_not("{}"), tag("}"));
+    map(inner, Part::Placeholder)(input)
+}
+
+// {repeat N arguments...}, the arguments may hold placeholders and other groups.
+fn parse_repeat(input: &str) -> IResult<&str, Part<'_>> {
+    let open = terminated(tag("{"), tuple((multispace0, tag("repeat"), multispace1)));
+    let count = is_not(" \t\r\n{}");
+    let argv = delimited(multispace1, separated_list1(multispace1, parse_group_argument), multispace0);
+    map(terminated(tuple((open, count, argv)), tag("}")), |(open, count, argv)| Part::Repeat(open, count, argv))(input)
+}
+
+// One argument of the command: adjacent strings and placeholders are concatenated, whitespace ends it.
+fn parse_argument(input: &str) -> IResult<&str, Vec<Part<'_>>> {
+    many1(alt((parse_quoted, parse_brace, parse_string, parse_repeat, parse_placeholder)))(input)
+}
+
+fn parse_group_argument(input: &str) -> IResult<&str, Vec<Part<'_>>> {
+    many1(alt((parse_quoted, parse_brace, parse_group_string, parse_repeat, parse_placeholder)))(input)
+}
 
-fn parse_placeholder(input: &str) -> IResult<&str, PlaceholderEnum> {
-    let inner = delimited(tag("{"), is_not("}"), tag("}"));
-    map(inner, PlaceholderEnum::new)(input)
+// What the parser could not read at the start of rest.
+fn syntax_error(rest: &str) -> Invalid {
+    if rest.starts_with(['"', '\'']) {
+        return Invalid::expected("unterminated string", format!("a closing {}", &rest[..1]));
+    }
+    if let Some(inner) = rest.strip_prefix('{') {
+        return match inner.find(['{', '}']).map(|i| &inner[i..i + 1]) {
+            Some("{") => "unexpected { in a placeholder, only {repeat} can hold placeholders".into(),
+            Some(_) => "invalid placeholder".into(),
+            None => Invalid::expected("unterminated placeholder", "}"),
+        };
+    }
+    "unexpected character".into()
 }
 
+pub(crate) fn parse_all(input: &str) -> Result<Vec<Vec<PlaceholderEnum>>, ParseError> {
+    let args = match all_consuming(delimited(multispace0, separated_list0(multispace1, parse_argument), multispace0))(input) {
+        Ok((_, args)) => args,
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            // all_consuming fails where the arguments stopped
+            let offset = input.len() - e.input.len();
+            return Err(ParseError::new(input, offset, syntax_error(e.input)));
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers"),
+    };
+    Builder { input, names: HashMap::new(), has_key: false }.build(args)
+}
 
-pub(crate) fn parse_all(input: &str) -> IResult<&str, Vec<PlaceholderEnum>> {
-    all_consuming(many0(alt((parse_string, parse_placeholder))))(input)
+// Builds the placeholders and checks them in the order they are generated.
+struct Builder<'a> {
+    input: &'a str,
+    names: HashMap<String, bool>, // bound name -> whether it generates several arguments
+    has_key: bool,                // an argument with a {key} is generated before
+}
+
+impl<'a> Builder<'a> {
+    fn error(&self, at: &str, invalid: impl Into<Invalid>) -> ParseError {
+        let offset = at.as_ptr() as usize - self.input.as_ptr() as usize;
+        ParseError::new(self.input, offset, invalid.into())
+    }
+
+    fn build(&mut self, args: Vec<Vec<Part<'a>>>) -> Result<Vec<Vec<PlaceholderEnum>>, ParseError> {
+        let mut argv = Vec::new();
+        for arg in args {
+            let mut phs = Vec::new();
+            let mut starts = Vec::new(); // where each part starts, for errors
+            for part in arg {
+                let (start, ph) = match part {
+                    Part::String(s, value) => {
+                        // adjacent strings, e.g. around an escaped brace, are generated as one
+                        if let Some(PlaceholderEnum::String(last)) = phs.last_mut() {
+                            last.extend(&value);
+                            continue;
+                        }
+                        (s, PlaceholderEnum::new_string(value))
+                    }
+                    Part::Placeholder(s) => {
+                        let start = &self.input[s.as_ptr() as usize - self.input.as_ptr() as usize - 1..];
+                        let ph = PlaceholderEnum::new(s).map_err(|e| self.error(start, e))?;
+                        (start, ph)
+                    }
+                    Part::Repeat(open, count, group) => {
+                        let group = self.build(group)?;
+                        (open, PlaceholderEnum::new_repeat(count, group).map_err(|e| self.error(count, e))?)
+                    }
+                };
+                if let Some(name) = ph.ref_name() {
+                    if !self.names.contains_key(name) {
+                        return Err(self.error(start, format!("{{ref {}}} must come after a placeholder named {}", name, name)));
+                    }
+                }
+                if let Some(name) = ph.name() {
+                    if self.names.insert(name.to_string(), ph.is_multi_arg()).is_some() {
+                        return Err(self.error(start, format!("placeholder name {} is used twice", name)));
+                    }
+                }
+                if ph.is_derived() && !self.has_key {
+                    return Err(self.error(start, "a value derived from the key must come after an argument with a {key} placeholder".to_string()));
+                }
+                phs.push(ph);
+                starts.push(start);
+            }
+            if phs.len() > 1 {
+                let is_multi_arg = |ph: &PlaceholderEnum| ph.is_multi_arg() || ph.ref_name().is_some_and(|name| self.names[name]);
+                if let Some(i) = phs.iter().position(is_multi_arg) {
+                    return Err(self.error(starts[i], "a placeholder that generates several arguments must be separated by spaces".to_string()));
+                }
+            }
+            self.has_key |= phs.iter().any(|ph| ph.is_key());
+            argv.push(phs);
+        }
+        Ok(argv)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command::placeholder::GenContext;
+    use crate::rng::FastRng;
+
+    fn gen(input: &str) -> Vec<Vec<Vec<u8>>> {
+        let mut args = parse_all(input).unwrap();
+        let mut rng = FastRng::from_entropy();
+        let mut context = GenContext::new(&mut rng, 0, 0);
+        args.iter_mut().map(|arg| arg.iter_mut().flat_map(|ph| ph.gen(&mut context)).collect()).collect()
+    }
 
     #[test]
     fn test_root() {
-        let (nm, args) = match parse_all("aa test_{key sequence 100} bbb") {
-            Ok((nm, args)) => (nm, args),
+        let args = match parse_all("aa test_{key sequence 100} bbb") {
+            Ok(args) => args,
             Err(e) => {
-                println!("Error: {:?}", e);
+                println!("Error: {}", e);
                 return;
             }
         };
-        println!("nm: {:?}, args: {:?}", nm, args);
+        println!("args: {:?}", args);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_arguments() {
+        let args = gen("  SET \"hello world\" 'a b'c {rand 1}\t\"\" ");
+        assert_eq!(args.len(), 5);
+        assert_eq!(args[0], vec![b"SET".to_vec()]);
+        assert_eq!(args[1], vec![b"hello world".to_vec()]);
+        assert_eq!(args[2], vec![b"a bc".to_vec()]);
+        assert_eq!(args[3], vec![b"0".to_vec()]);
+        assert_eq!(args[4], vec![b"".to_vec()]);
+
+        let args = gen("JSON.SET k $ '{\"a\": [1, 2]}'");
+        assert_eq!(args[3], vec![b"{\"a\": [1, 2]}".to_vec()]);
+
+        let args = gen("ZRANGE k {range 10 3} x_{key sequence 5}_y");
+        assert_eq!(args[2].len(), 2);
+        assert_eq!(args[3].concat(), b"x_key_0000000000_y".to_vec());
+
+        let args = gen("HSET {key:k uniform 100} owner {ref k}");
+        assert_eq!(args[1], args[3]);
+
+        let args = gen("MSET {repeat 2 k{rand 1} \"a }\"} {repeat 1-1  {repeat 2 x}  } end");
+        assert_eq!(args.concat(), ["MSET", "k0", "a }", "k0", "a }", "x", "x", "end"].map(|s| s.as_bytes().to_vec()));
+
+        assert!(gen("").is_empty());
+        assert!(parse_all("SET \"unterminated").is_err());
+        assert!(parse_all("MSET {repeat 2 {key uniform 10}").is_err());
+        assert!(parse_all("SET {key uniform {rand 1}}").is_err());
+    }
+
+    #[test]
+    fn test_escapes() {
+        let arg = |input: &str| gen(input).remove(0).concat();
+        // literal braces outside of quotes
+        assert_eq!(arg("user:{{42}}"), b"user:{42}");
+        assert_eq!(arg("user:{{{rand 1}}}:x"), b"user:{0}:x");
+        assert_eq!(arg("a}b"), b"a}b");
+        assert_eq!(gen("{{rand 1}}"), vec![vec![b"{rand".to_vec()], vec![b"1}".to_vec()]]);
+        assert_eq!(gen("MSET {repeat 2 {{{rand 1}}}}")[1], vec![b"{0}".to_vec(), b"{0}".to_vec()]);
+        // braces in quotes are literal, doubled or not
+        assert_eq!(arg("'{{x}}'"), b"{{x}}");
+        assert_eq!(arg("\"{key uniform 10}\""), b"{key uniform 10}");
+
+        // double quotes
+        assert_eq!(arg(r#""a\"b""#), b"a\"b");
+        assert_eq!(arg(r#""a\\b""#), b"a\\b");
+        assert_eq!(arg(r#""\n\r\t\b\a""#), b"\n\r\t\x08\x07");
+        assert_eq!(arg(r#""\x00\xff\x41\xFe""#), b"\x00\xffA\xfe");
+        assert_eq!(arg(r#""\xg1\x4""#), b"xg1x4");
+        assert_eq!(arg(r#""\{\'é""#), "{'é".as_bytes());
+        assert_eq!(arg(r#""it's""#), b"it's");
+        // single quotes only escape the quote
+        assert_eq!(arg(r#"'a\'b'"#), b"a'b");
+        assert_eq!(arg(r#"'\n\x41\ '"#), b"\\n\\x41\\ ");
+        assert_eq!(arg(r#"'say "hi"'"#), b"say \"hi\"");
+
+        assert!(parse_all(r#"SET k "a\""#).is_err());
+        assert!(parse_all(r#"SET k 'a\'"#).is_err());
+        assert!(parse_all(r#"SET k "a\"#).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &str| {
+            let e = parse_all(input).unwrap_err();
+            println!("{}", e);
+            (e.offset, e.message)
+        };
+        let expected = |input: &str| parse_all(input).unwrap_err().expected;
+        assert_eq!(error("SET k \"abc"), (6, "unterminated string".to_string()));
+        assert_eq!(expected("SET k \"abc"), Some("a closing \"".to_string()));
+        assert_eq!(error("SET {key uniform 10"), (4, "unterminated placeholder".to_string()));
+        assert_eq!(expected("SET {key uniform 10"), Some("}".to_string()));
+        assert_eq!(error("SET {key uniform {rand 1}}").0, 4);
+        assert_eq!(expected("SET {key uniform {rand 1}}"), None);
+        assert_eq!(error("GET a{key uniform x}"), (5, "invalid N: x".to_string()));
+        assert_eq!(expected("GET a{key uniform x}"), Some("a non-negative integer".to_string()));
+        assert_eq!(expected("GET {rand}"), Some("{rand N}".to_string()));
+        assert_eq!(error("GET {key pareto 10}"), (4, "unknown distribution: pareto".to_string()));
+        assert_eq!(expected("GET {key pareto 10}"), Some("uniform, zipfian, sequence, gaussian, hotspot, latest or exponential".to_string()));
+        assert_eq!(expected("GET {key sequence 10 shard=3/2}"), Some("I/N with I < N".to_string()));
+        assert_eq!(error("GET {kye uniform 10}").0, 4);
+        assert_eq!(error("GET {rand 0}").0, 4);
+        assert_eq!(expected("GET {rand 0}"), None);
+        assert_eq!(error("ZRANGE k {range 10 10}").0, 9);
+        assert_eq!(error("MSET {repeat x {rand 10}}").0, 13);
+        assert_eq!(error("MSET {repeat 2 {value 8} {value x}}").0, 25);
+        assert_eq!(error("MSET {repeat 2}").0, 5);
+        assert_eq!(error("SET {ref k} {key:k uniform 10}").0, 4);
+        assert_eq!(error("SET {key:k uniform 10} {value:k 8}").0, 23);
+        assert_eq!(error("ZRANGE k x{range 10 3}").0, 10);
+        assert_eq!(error("SET k {value 8 content=derived}").0, 6);
+
+        // the caret is under the offset, counted in characters
+        let e = parse_all("SET é {rand x}").unwrap_err();
+        assert_eq!(e.to_string(), "invalid N: x, expected a non-negative integer at offset 7\n  SET é {rand x}\n        ^");
+    }
+}
@@ -1,8 +1,15 @@
 use std::cmp::min;
-use std::process::exit;
-use crate::command::distribution::DistributionEnum;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::command::distribution::{self, DistributionEnum};
+use crate::command::parser::Invalid;
+use crate::rng::{FastRng, SplitMix64};
 use std::str::FromStr;
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rand::distributions::{Alphanumeric, WeightedIndex};
+use rand::Rng;
 
 #[derive(Debug, Clone)]
 pub enum PlaceholderEnum {
@@ -11,113 +18,555 @@ pub enum PlaceholderEnum {
     Value(PlaceholderValue),
     Rand(PlaceholderRand),
     Range(PlaceholderRange),
+    Repeat(PlaceholderRepeat),
+    Float(PlaceholderFloat),
+    Now(PlaceholderNow),
+    Choice(PlaceholderChoice),
+    File(PlaceholderFile),
+    Uuid,
+    ConnId,   // index of the connection among all connections of the run
+    ThreadId, // index of the thread running the connection
+    Named(String, Box<PlaceholderEnum>), // {key:k1 uniform 100}, its values are kept for {ref k1}
+    Ref(String),                         // {ref k1}, the values of the named placeholder in the same command
+}
+
+// State of the command being generated, shared by its placeholders.
+pub struct GenContext<'a> {
+    rng: &'a mut FastRng, // of the command on its connection
+    key: Vec<u8>,         // the last generated argument that holds a {key}, for derived values
+    bound: HashMap<String, Vec<Vec<u8>>>,
+    pub value_bytes: u64, // bytes generated by {value}
+    conn_id: u64,
+    thread_id: u64,
+}
+
+impl<'a> GenContext<'a> {
+    pub fn new(rng: &'a mut FastRng, conn_id: u64, thread_id: u64) -> Self {
+        Self { rng, key: Vec::new(), bound: HashMap::new(), value_bytes: 0, conn_id, thread_id }
+    }
+}
+
+// Generates the arguments, each a list of parts that are concatenated, except a single part may expand to several arguments.
+pub fn gen_args(argv: &mut [Vec<PlaceholderEnum>], context: &mut GenContext, out: &mut Vec<Vec<u8>>) {
+    for arg in argv.iter_mut() {
+        let has_key = arg.iter().any(|ph| ph.is_key());
+        match arg.as_mut_slice() {
+            [ph] => {
+                for value in ph.gen(context) {
+                    if has_key {
+                        context.key.clone_from(&value);
+                    }
+                    out.push(value);
+                }
+            }
+            parts => {
+                let mut value = Vec::new();
+                for ph in parts {
+                    for part in ph.gen(context) {
+                        value.extend_from_slice(&part);
+                    }
+                }
+                if has_key {
+                    context.key.clone_from(&value);
+                }
+                out.push(value);
+            }
+        }
+    }
+}
+
+// Like gen_args, but writes the arguments straight to out as RESP bulk strings and returns their number. Constant
+// arguments are copied as encoded when the command was parsed.
+pub fn encode_args(argv: &mut [Vec<PlaceholderEnum>], context: &mut GenContext, out: &mut Vec<u8>) -> u64 {
+    let mut argc = 0;
+    for arg in argv.iter_mut() {
+        let start = out.len();
+        match arg.as_mut_slice() {
+            [ph] => argc += ph.encode(context, out),
+            parts => {
+                write_bulk_with(out, |out| parts.iter_mut().for_each(|ph| ph.gen_into(context, out)));
+                argc += 1;
+            }
+        }
+        if arg.iter().any(|ph| ph.is_key()) {
+            let bulk = &out[start..out.len() - 2];
+            let header_len = bulk.iter().position(|b| *b == b'\n').unwrap() + 1;
+            context.key.clear();
+            context.key.extend_from_slice(&bulk[header_len..]);
+        }
+    }
+    argc
+}
+
+// The number of arguments generated from argv, None when it varies with the count of a {repeat MIN-MAX}. named
+// holds the numbers of the named placeholders before argv.
+pub fn fixed_argc(argv: &[Vec<PlaceholderEnum>], named: &mut HashMap<String, u64>) -> Option<u64> {
+    let mut argc = 0;
+    for arg in argv {
+        argc += match arg.as_slice() {
+            [ph] => ph.fixed_argc(named)?,
+            _ => 1,
+        };
+    }
+    Some(argc)
+}
+
+pub fn write_bulk(out: &mut Vec<u8>, value: &[u8]) {
+    write!(out, "${}\r\n", value.len()).unwrap();
+    out.extend_from_slice(value);
+    out.extend_from_slice(b"\r\n");
+}
+
+// Appends a bulk string written by f, the header is put in front of it once the length is known.
+fn write_bulk_with(out: &mut Vec<u8>, f: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    f(out);
+    let mut header = std::io::Cursor::new([0u8; 24]);
+    write!(header, "${}\r\n", out.len() - start).unwrap();
+    let header_len = header.position() as usize;
+    out.splice(start..start, header.get_ref()[..header_len].iter().copied());
+    out.extend_from_slice(b"\r\n");
 }
 
 impl PlaceholderEnum {
-    pub fn new_string(str: &str) -> Self {
-        Self::String(PlaceholderString::new(str.to_string()))
+    pub fn new_string(value: Vec<u8>) -> Self {
+        Self::String(PlaceholderString::new(value))
+    }
+    pub fn new_repeat(count: &str, argv: Vec<Vec<PlaceholderEnum>>) -> Result<Self, Invalid> {
+        Ok(Self::Repeat(PlaceholderRepeat::new(count, argv)?))
     }
-    pub fn new(str: &str) -> Self {
-        let s = str.to_string();
-        let words: Vec<&str> = s.split_whitespace().collect();
-        if words.len() == 0 {
-            eprint!("placeholder is empty");
-            exit(1);
+    // str is the text inside the braces, the error does not repeat it.
+    pub fn new(str: &str) -> Result<Self, Invalid> {
+        let words: Vec<&str> = str.split_whitespace().collect();
+        if words.is_empty() {
+            return Err("empty placeholder".into());
         }
-        let ph = match words[0] {
+        let (kind, name) = match words[0].split_once(':') {
+            Some((kind, name)) => (kind, Some(name)),
+            None => (words[0], None),
+        };
+        let args = &words[1..];
+        let arg_cnt = |expected: &str, ok: bool| if ok { Ok(()) } else { Err(Invalid::expected(format!("wrong number of arguments for {}", kind), format!("{{{}}}", expected))) };
+        let ph = match kind {
             "key" => {
-                if words.len() != 3 {
-                    eprint!("wrong number of arguments for key placeholder: {:?}", words);
-                    exit(1);
+                arg_cnt("key DISTRIBUTION N [options]", args.len() >= 2)?;
+                let range = parse_u64("N", args[1])?;
+                let (distribution_options, key_options): (Vec<&str>, Vec<&str>) =
+                    args[2..].iter().partition(|option| distribution::OPTIONS.contains(&option.split('=').next().unwrap()));
+                let mut key = PlaceholderKey::new(DistributionEnum::new(args[0], range, &distribution_options)?);
+                for option in &key_options {
+                    key.set_option(option)?;
                 }
-                let range = u64::from_str(words[2]).unwrap();
-                let distribution = DistributionEnum::new(words[1], range);
-                PlaceholderEnum::Key(PlaceholderKey::new(distribution))
+                PlaceholderEnum::Key(key)
             }
             "value" => {
-                if words.len() != 2 {
-                    eprint!("wrong number of arguments for value placeholder: {:?}", words);
-                    exit(1);
-                }
-                let size = u64::from_str(words[1]).unwrap();
-                PlaceholderEnum::Value(PlaceholderValue::new(size))
+                arg_cnt("value SIZE [options]", !args.is_empty())?;
+                PlaceholderEnum::Value(PlaceholderValue::new(args)?)
             }
             "rand" => {
-                if words.len() != 2 {
-                    eprint!("wrong number of arguments for rand placeholder: {:?}", words);
-                    exit(1);
-                }
-                PlaceholderEnum::Rand(PlaceholderRand::new(u64::from_str(words[1]).unwrap()))
+                arg_cnt("rand N", args.len() == 1)?;
+                PlaceholderEnum::Rand(PlaceholderRand::new(parse_u64("N", args[0])?)?)
             }
             "range" => {
-                if words.len() != 3 {
-                    eprint!("wrong number of arguments for range placeholder: {:?}", words);
-                    exit(1);
+                arg_cnt("range N W", args.len() == 2)?;
+                PlaceholderEnum::Range(PlaceholderRange::new(parse_u64("N", args[0])?, parse_u64("W", args[1])?)?)
+            }
+            "float" => {
+                arg_cnt("float MIN MAX", args.len() == 2)?;
+                PlaceholderEnum::Float(PlaceholderFloat::new(args[0], args[1])?)
+            }
+            "now_ms" | "now_us" => {
+                let monotonic = match args {
+                    [] => false,
+                    ["monotonic"] => true,
+                    _ => return Err(Invalid::expected(format!("unexpected arguments for {}", kind), format!("{{{}}} or {{{} monotonic}}", kind, kind))),
+                };
+                PlaceholderEnum::Now(PlaceholderNow::new(kind == "now_us", monotonic))
+            }
+            "choice" => PlaceholderEnum::Choice(PlaceholderChoice::new(args)?),
+            "file" => {
+                arg_cnt("file PATH [DISTRIBUTION] [options]", !args.is_empty())?;
+                PlaceholderEnum::File(PlaceholderFile::new(args)?)
+            }
+            "uuid" | "conn_id" | "thread_id" => {
+                arg_cnt(kind, args.is_empty())?;
+                match kind {
+                    "uuid" => PlaceholderEnum::Uuid,
+                    "conn_id" => PlaceholderEnum::ConnId,
+                    _ => PlaceholderEnum::ThreadId,
                 }
-                let range = u64::from_str(words[1]).unwrap();
-                let width = u64::from_str(words[2]).unwrap();
-                PlaceholderEnum::Range(PlaceholderRange::new(range, width))
             }
-            name => {
-                eprint!("Invalid placeholder: {}", name);
-                exit(1);
+            "ref" if name.is_none() => {
+                arg_cnt("ref NAME", args.len() == 1)?;
+                PlaceholderEnum::Ref(args[0].to_string())
             }
+            // a {repeat} that did not parse as a group
+            "repeat" => return Err(Invalid::expected("{repeat} without arguments", "{repeat N arguments...}")),
+            _ => return Err(Invalid::expected(format!("unknown placeholder: {}", kind), "key, value, rand, range, float, now_ms, now_us, choice, file, uuid, conn_id, thread_id, ref or repeat")),
         };
-        ph
+        match name {
+            Some(name) if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => Err(Invalid::expected(format!("invalid placeholder name: {:?}", name), "letters, digits and _")),
+            Some(name) => Ok(PlaceholderEnum::Named(name.to_string(), Box::new(ph))),
+            None => Ok(ph),
+        }
+    }
+    // The placeholder generating the values, seen through a name.
+    fn inner(&self) -> &Self {
+        match self {
+            Self::Named(_, p) => p.inner(),
+            p => p,
+        }
+    }
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::Named(name, _) => Some(name),
+            _ => None,
+        }
+    }
+    pub fn ref_name(&self) -> Option<&str> {
+        match self {
+            Self::Ref(name) => Some(name),
+            _ => None,
+        }
+    }
+    // Placeholders that expand to several arguments can not be joined with text or other placeholders.
+    pub fn is_multi_arg(&self) -> bool {
+        matches!(self.inner(), Self::Range(_) | Self::Repeat(_))
+    }
+    pub fn is_key(&self) -> bool {
+        matches!(self.inner(), Self::Key(_))
+    }
+    pub fn is_derived(&self) -> bool {
+        matches!(self.inner(), Self::Value(PlaceholderValue { content: Content::Derived, .. }))
+    }
+    // Whether a sequence can not be reserved, because it is in a {repeat} with a count range.
+    pub fn has_unreserved_sequence(&self) -> bool {
+        match self {
+            Self::Repeat(p) if p.min != p.max => p.argv.iter().flatten().any(|p| p.has_sequence()),
+            Self::Repeat(p) => p.argv.iter().flatten().any(|p| p.has_unreserved_sequence()),
+            _ => false,
+        }
+    }
+    fn has_sequence(&self) -> bool {
+        match self {
+            Self::Key(p) => p.distribution.is_sequence(),
+            Self::Value(PlaceholderValue { size: ValueSize::Distribution(_, d), .. }) => d.is_sequence(),
+            Self::File(p) => p.distribution.is_sequence(),
+            Self::Repeat(p) => p.argv.iter().flatten().any(|p| p.has_sequence()),
+            Self::Named(_, p) => p.has_sequence(),
+            _ => false,
+        }
+    }
+    // Reserves the generations start..start + n of the command for the sequences, see Command::reserve.
+    pub fn reserve(&mut self, start: u64, n: u64) {
+        match self {
+            Self::Key(p) => p.distribution.reserve(start, n),
+            Self::Value(PlaceholderValue { size: ValueSize::Distribution(_, d), .. }) => d.reserve(start, n),
+            Self::File(p) => p.distribution.reserve(start, n),
+            Self::Repeat(p) => p.reserve(start, n),
+            Self::Named(_, p) => p.reserve(start, n),
+            _ => {}
+        }
+    }
+    pub fn gen(&mut self, context: &mut GenContext) -> Vec<Vec<u8>> {
+        match self {
+            Self::Range(p) => p.sample(context.rng).iter().map(|x| x.to_string().into_bytes()).collect(),
+            Self::Repeat(p) => p.gen(context),
+            Self::Named(name, p) => {
+                let values = p.gen(context);
+                context.bound.insert(name.clone(), values.clone());
+                values
+            }
+            // the command checks that the name is bound before
+            Self::Ref(name) => context.bound[name.as_str()].clone(),
+            p => {
+                let mut value = Vec::new();
+                p.gen_into(context, &mut value);
+                vec![value]
+            }
+        }
     }
-    pub fn gen(&mut self) -> Vec<String> {
+    // Appends the value of a placeholder that generates a single argument to out.
+    fn gen_into(&mut self, context: &mut GenContext, out: &mut Vec<u8>) {
         match self {
-            Self::String(p) => vec![p.gen()],
-            Self::Key(p) => vec![p.gen()],
-            Self::Value(p) => vec![p.gen()],
-            Self::Rand(p) => vec![p.gen()],
-            Self::Range(p) => p.gen(),
+            Self::String(p) => out.extend_from_slice(&p.value),
+            Self::Key(p) => p.gen_into(context.rng, out),
+            Self::Value(p) => context.value_bytes += p.gen_into(&context.key, context.rng, out, false) as u64,
+            Self::Rand(p) => p.gen_into(context.rng, out),
+            Self::Float(p) => p.gen_into(context.rng, out),
+            Self::Now(p) => p.gen_into(out),
+            Self::Choice(p) => p.gen_into(context.rng, out),
+            Self::File(p) => p.gen_into(context.rng, out),
+            Self::Uuid => gen_uuid(context.rng, out),
+            Self::ConnId => write!(out, "{}", context.conn_id).unwrap(),
+            Self::ThreadId => write!(out, "{}", context.thread_id).unwrap(),
+            p => p.gen(context).iter().for_each(|value| out.extend_from_slice(value)),
+        }
+    }
+    // Writes the arguments of a placeholder that is a whole argument to out as RESP bulk strings, returns their number.
+    fn encode(&mut self, context: &mut GenContext, out: &mut Vec<u8>) -> u64 {
+        match self {
+            Self::String(p) => out.extend_from_slice(&p.encoded),
+            // the size is known before the content, so the value is written after its header
+            Self::Value(p) => context.value_bytes += p.gen_into(&context.key, context.rng, out, true) as u64,
+            Self::Range(p) => {
+                for x in p.sample(context.rng) {
+                    write_bulk_with(out, |out| write!(out, "{}", x).unwrap());
+                }
+                return 2;
+            }
+            Self::Repeat(p) => return p.encode(context, out),
+            Self::Named(..) | Self::Ref(_) => {
+                let values = self.gen(context);
+                values.iter().for_each(|value| write_bulk(out, value));
+                return values.len() as u64;
+            }
+            p => write_bulk_with(out, |out| p.gen_into(context, out)),
+        }
+        1
+    }
+    fn fixed_argc(&self, named: &mut HashMap<String, u64>) -> Option<u64> {
+        match self {
+            Self::Range(_) => Some(2),
+            Self::Repeat(p) if p.min == p.max => p.min.checked_mul(fixed_argc(&p.argv, named)?),
+            Self::Repeat(_) => None,
+            Self::Named(name, p) => {
+                let argc = p.fixed_argc(named)?;
+                named.insert(name.clone(), argc);
+                Some(argc)
+            }
+            Self::Ref(name) => named.get(name).copied(),
+            _ => Some(1),
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct PlaceholderString {
-    value: String,
+    value: Vec<u8>,
+    encoded: Vec<u8>, // the value as a RESP bulk string
 }
 
 impl PlaceholderString {
-    pub fn new(value: String) -> Self {
-        Self { value }
+    pub fn new(value: Vec<u8>) -> Self {
+        let mut encoded = Vec::new();
+        write_bulk(&mut encoded, &value);
+        Self { value, encoded }
     }
-    fn gen(&mut self) -> String {
-        self.value.clone()
+    pub fn extend(&mut self, value: &[u8]) {
+        *self = Self::new([self.value.as_slice(), value].concat());
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+enum HashTag {
+    None,
+    Id,            // prefix{id}, keys generated from the same id go to the same slot
+    Fixed(String), // {tag}prefix_id, all keys go to the same slot
+}
+
+// Options are given as `name=value` after the range, e.g. {key uniform 100 prefix=user: width=0 format=hex hashtag}
 #[derive(Clone, Debug)]
 pub struct PlaceholderKey {
     distribution: DistributionEnum,
+    prefix: String,
+    width: usize, // zero-padded to this many digits
+    hex: bool,
+    hash_tag: HashTag,
 }
 
 impl PlaceholderKey {
     fn new(distribution: DistributionEnum) -> Self {
-        Self { distribution }
+        Self {
+            distribution,
+            prefix: "key_".to_string(),
+            width: 10,
+            hex: false,
+            hash_tag: HashTag::None,
+        }
+    }
+    fn set_option(&mut self, option: &str) -> Result<(), Invalid> {
+        match option.split_once('=') {
+            Some(("prefix", prefix)) => self.prefix = prefix.to_string(),
+            Some(("width", width)) => self.width = width.parse().map_err(|_| format!("invalid width: {}", width))?,
+            Some(("format", "dec")) => self.hex = false,
+            Some(("format", "hex")) => self.hex = true,
+            Some(("format", format)) => return Err(Invalid::expected(format!("invalid format: {}", format), "dec or hex")),
+            Some(("hashtag", tag)) => self.hash_tag = HashTag::Fixed(tag.to_string()),
+            None if option == "hashtag" => self.hash_tag = HashTag::Id,
+            _ => return Err(format!("unknown option: {}", option).into()),
+        }
+        Ok(())
     }
-    fn gen(&mut self) -> String {
-        format!("key_{:010}", self.distribution.sample(&mut rand::thread_rng()))
+    fn gen_into(&mut self, rng: &mut FastRng, out: &mut Vec<u8>) {
+        let id = self.distribution.sample(rng);
+        let width = self.width;
+        match &self.hash_tag {
+            HashTag::None => out.extend_from_slice(self.prefix.as_bytes()),
+            HashTag::Id => write!(out, "{}{{", self.prefix).unwrap(),
+            HashTag::Fixed(tag) => write!(out, "{{{}}}{}", tag, self.prefix).unwrap(),
+        }
+        if self.hex {
+            write!(out, "{:0width$x}", id).unwrap();
+        } else {
+            write!(out, "{:0width$}", id).unwrap();
+        }
+        if self.hash_tag == HashTag::Id {
+            out.push(b'}');
+        }
     }
 }
 
+// the largest bulk string Redis accepts by default
+const MAX_VALUE_SIZE: u64 = 512 * 1024 * 1024;
+// compressible content repeats within blocks of this size, well inside the 8KB window of LZF
+const COMPRESSIBLE_BLOCK: usize = 256;
+
+#[derive(Clone, Debug)]
+enum ValueSize {
+    Fixed(usize),
+    Distribution(u64, DistributionEnum), // min size plus a sample of the distribution
+    Weighted(Vec<usize>, WeightedIndex<u64>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Content {
+    Alphanumeric,
+    Bytes,             // all 256 byte values
+    Compressible(f64), // alphanumeric, only this fraction of the value is unique, the rest repeats it
+    Fixed,             // 'x' repeated, the same value every time like redis-benchmark
+    Numeric,           // decimal digits without a leading zero
+    Derived,           // alphanumeric, size and content are seeded from the key, so the same key gets the same value
+}
+
+// {value 64}, {value 64-4096} uniform from 64 to 4096 bytes, {value 64-4096 zipfian exponent=1.2} with a key distribution
+// over the sizes, or {value 64:80,1024:15,65536:5} for weighted sizes.
+// The content is set with content=alphanumeric|bytes|compressible|fixed|numeric|derived, and ratio=R for compressible.
 #[derive(Clone, Debug)]
 pub struct PlaceholderValue {
-    size: usize,
+    size: ValueSize,
+    content: Content,
 }
 
 impl PlaceholderValue {
-    pub fn new(size: u64) -> Self {
-        Self { size: size as usize }
+    pub fn new(words: &[&str]) -> Result<Self, Invalid> {
+        let (value_options, words): (Vec<&str>, Vec<&str>) = words.iter().partition(|word| word.starts_with("content=") || word.starts_with("ratio="));
+        if words.is_empty() {
+            return Err("missing size".into());
+        }
+        let mut content = "alphanumeric";
+        let mut ratio = None;
+        for option in value_options {
+            match option.split_once('=') {
+                Some(("content", value)) => content = value,
+                Some(("ratio", value)) => ratio = Some(value.parse::<f64>().ok().filter(|r| *r > 0.0 && *r <= 1.0).ok_or_else(|| format!("ratio must be greater than 0 and at most 1, got {}", value))?),
+                _ => unreachable!(),
+            }
+        }
+        let content = match content {
+            "alphanumeric" => Content::Alphanumeric,
+            "bytes" => Content::Bytes,
+            "compressible" => Content::Compressible(ratio.unwrap_or(0.5)),
+            "fixed" => Content::Fixed,
+            "numeric" => Content::Numeric,
+            "derived" => Content::Derived,
+            _ => return Err(Invalid::expected(format!("unknown content: {}", content), "alphanumeric, bytes, compressible, fixed, numeric or derived")),
+        };
+        if ratio.is_some() && !matches!(content, Content::Compressible(_)) {
+            return Err("ratio is only supported by compressible content".into());
+        }
+        let parse = |s: &str| match u64::from_str(s) {
+            Ok(size) if size > MAX_VALUE_SIZE => Err(Invalid::expected(format!("size too large: {}", s), format!("at most {} (512MB, the proto-max-bulk-len of Redis)", MAX_VALUE_SIZE))),
+            Ok(size) => Ok(size),
+            Err(_) => Err(Invalid::from(format!("invalid size: {}", s))),
+        };
+        let size = if words[0].contains([':', ',']) {
+            if words.len() > 1 {
+                return Err("weighted sizes take no options".into());
+            }
+            let mut sizes = Vec::new();
+            let mut weights = Vec::new();
+            for item in words[0].split(',') {
+                let (size, weight) = item.split_once(':').unwrap_or((item, "1"));
+                sizes.push(parse(size)? as usize);
+                weights.push(u64::from_str(weight).map_err(|_| format!("invalid weight: {}", weight))?);
+            }
+            ValueSize::Weighted(sizes, WeightedIndex::new(weights).map_err(|e| e.to_string())?)
+        } else if let Some((min, max)) = words[0].split_once('-') {
+            let (min, max) = (parse(min)?, parse(max)?);
+            if min > max {
+                return Err(format!("invalid size range: {}", words[0]).into());
+            }
+            let (name, options) = match words.get(1) {
+                Some(name) if !name.contains('=') => (*name, &words[2..]),
+                _ => ("uniform", &words[1..]),
+            };
+            // the mean is a size, the distribution samples an offset from min
+            let options: Vec<String> = options
+                .iter()
+                .map(|option| match option.split_once('=') {
+                    Some(("mean", mean)) => mean.parse::<f64>().map(|mean| format!("mean={}", mean - min as f64)).unwrap_or(option.to_string()),
+                    _ => option.to_string(),
+                })
+                .collect();
+            let options: Vec<&str> = options.iter().map(String::as_str).collect();
+            ValueSize::Distribution(min, DistributionEnum::new(name, max - min + 1, &options)?)
+        } else {
+            if words.len() > 1 {
+                return Err("a fixed size takes no options".into());
+            }
+            ValueSize::Fixed(parse(words[0])? as usize)
+        };
+        Ok(Self { size, content })
     }
-    pub fn gen(&self) -> String {
-        let rng = thread_rng();
-        let chars: String = rng.sample_iter(&Alphanumeric).take(self.size).map(char::from).collect();
-        chars
+    // Appends a value to out, as a RESP bulk string with bulk, and returns its size.
+    pub fn gen_into(&mut self, key: &[u8], rng: &mut FastRng, out: &mut Vec<u8>, bulk: bool) -> usize {
+        if self.content == Content::Derived {
+            self.gen_with(&mut SplitMix64::from_bytes(key), out, bulk)
+        } else {
+            self.gen_with(rng, out, bulk)
+        }
     }
+    fn gen_with(&mut self, rng: &mut impl Rng, out: &mut Vec<u8>, bulk: bool) -> usize {
+        let size = match &mut self.size {
+            ValueSize::Fixed(size) => *size,
+            ValueSize::Distribution(min, distribution) => (*min + distribution.sample(rng)) as usize,
+            ValueSize::Weighted(sizes, choose) => sizes[rng.sample(&*choose)],
+        };
+        if bulk {
+            write!(out, "${}\r\n", size).unwrap();
+        }
+        let start = out.len();
+        match self.content {
+            Content::Alphanumeric | Content::Derived => out.extend(rng.sample_iter(&Alphanumeric).take(size)),
+            Content::Bytes => {
+                out.resize(start + size, 0);
+                rng.fill_bytes(&mut out[start..]);
+            }
+            // Block by block, so the repeats stay within the window of the compressor at any value size.
+            Content::Compressible(ratio) => {
+                while out.len() < start + size {
+                    let block_start = out.len();
+                    let block = COMPRESSIBLE_BLOCK.min(start + size - block_start);
+                    let unique = ((block as f64 * ratio).ceil() as usize).max(1);
+                    out.extend(rng.sample_iter(&Alphanumeric).take(unique));
+                    while out.len() < block_start + block {
+                        out.extend_from_within(block_start..block_start + unique.min(block_start + block - out.len()));
+                    }
+                }
+            }
+            Content::Fixed => out.resize(start + size, b'x'),
+            Content::Numeric => out.extend((0..size).map(|i| if i == 0 && size > 1 { rng.gen_range(b'1'..=b'9') } else { rng.gen_range(b'0'..=b'9') })),
+        }
+        if bulk {
+            out.extend_from_slice(b"\r\n");
+        }
+        size
+    }
+}
+
+fn parse_u64(name: &str, s: &str) -> Result<u64, Invalid> {
+    u64::from_str(s).map_err(|_| Invalid::expected(format!("invalid {}: {}", name, s), "a non-negative integer"))
 }
 
 #[derive(Clone, Debug)]
@@ -126,11 +575,11 @@ pub struct PlaceholderRand {
 }
 
 impl PlaceholderRand {
-    pub fn new(range: u64) -> Self {
-        Self { distribution: DistributionEnum::new("uniform", range) }
+    pub fn new(range: u64) -> Result<Self, Invalid> {
+        Ok(Self { distribution: DistributionEnum::new("uniform", range, &[])? })
     }
-    fn gen(&mut self) -> String {
-        format!("{}", self.distribution.sample(&mut thread_rng()))
+    fn gen_into(&mut self, rng: &mut FastRng, out: &mut Vec<u8>) {
+        write!(out, "{}", self.distribution.sample(rng)).unwrap();
     }
 }
 
@@ -142,17 +591,356 @@ pub struct PlaceholderRange {
 }
 
 impl PlaceholderRange {
-    pub fn new(range: u64, width: u64) -> Self {
-        Self {
-            distribution: DistributionEnum::new("uniform", range),
+    pub fn new(range: u64, width: u64) -> Result<Self, Invalid> {
+        if width >= range {
+            return Err(format!("W must be less than N, got N {} and W {}", range, width).into());
+        }
+        Ok(Self {
+            distribution: DistributionEnum::new("uniform", range, &[])?,
             range,
             width,
+        })
+    }
+    fn sample(&mut self, rng: &mut FastRng) -> [u64; 2] {
+        let left = self.distribution.sample(rng);
+        [left, min(left + self.width, self.range - 1)]
+    }
+}
+
+// {repeat N ...} or {repeat MIN-MAX ...} with N uniform between MIN and MAX: the arguments of the group are generated N times,
+// e.g. MSET {repeat 10 {key uniform 1000} {value 64}}
+#[derive(Clone, Debug)]
+pub struct PlaceholderRepeat {
+    min: u64,
+    max: u64,
+    distribution: DistributionEnum,
+    argv: Vec<Vec<PlaceholderEnum>>,
+}
+
+impl PlaceholderRepeat {
+    fn new(count: &str, argv: Vec<Vec<PlaceholderEnum>>) -> Result<Self, Invalid> {
+        let parse = |s: &str| u64::from_str(s).map_err(|_| format!("invalid count: {}", s));
+        let (min, max) = match count.split_once('-') {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(count)?, parse(count)?),
+        };
+        if min > max {
+            return Err(format!("invalid count range: {}", count).into());
+        }
+        let n = (max - min).checked_add(1).ok_or_else(|| format!("count range too large: {}, it spans more than 2^64 - 1 counts", count))?;
+        Ok(Self { min, max, distribution: DistributionEnum::new("uniform", n, &[])?, argv })
+    }
+    // Each generation of the command generates the parts count times. With a count range the number is not known
+    // beforehand, the sequences inside then take their values from their counters.
+    fn reserve(&mut self, start: u64, n: u64) {
+        if self.min == self.max {
+            self.argv.iter_mut().flatten().for_each(|p| p.reserve(start * self.min, n * self.min));
+        }
+    }
+    fn gen(&mut self, context: &mut GenContext) -> Vec<Vec<u8>> {
+        let n = self.min + self.distribution.sample(context.rng);
+        let mut args = Vec::new();
+        for _ in 0..n {
+            gen_args(&mut self.argv, context, &mut args);
         }
+        args
     }
-    fn gen(&mut self) -> Vec<String> {
-        let left = self.distribution.sample(&mut thread_rng());
-        let right = min(left + self.width, self.range - 1);
-        vec![left.to_string(), right.to_string()]
+    fn encode(&mut self, context: &mut GenContext, out: &mut Vec<u8>) -> u64 {
+        let n = self.min + self.distribution.sample(context.rng);
+        (0..n).map(|_| encode_args(&mut self.argv, context, out)).sum()
     }
 }
 
+// {float MIN MAX}, uniform in [MIN, MAX)
+#[derive(Clone, Debug)]
+pub struct PlaceholderFloat {
+    min: f64,
+    max: f64,
+}
+
+impl PlaceholderFloat {
+    fn new(min: &str, max: &str) -> Result<Self, String> {
+        let parse = |s: &str| s.parse::<f64>().ok().filter(|x| x.is_finite()).ok_or_else(|| format!("invalid number: {}", s));
+        let (min, max) = (parse(min)?, parse(max)?);
+        if min > max {
+            return Err(format!("min {} is greater than max {}", min, max));
+        }
+        // gen_range panics on a range wider than f64 can hold
+        if !(max - min).is_finite() {
+            return Err(format!("range too large: {} to {}", min, max));
+        }
+        Ok(Self { min, max })
+    }
+    fn gen_into(&mut self, rng: &mut FastRng, out: &mut Vec<u8>) {
+        let x = if self.min == self.max { self.min } else { rng.gen_range(self.min..self.max) };
+        write!(out, "{}", x).unwrap();
+    }
+}
+
+// {now_ms} or {now_us}, the unix time. With monotonic every request of the run gets a larger time than the one before,
+// running ahead of the clock when requests come faster than the unit, e.g. for XADD ids and TS.ADD timestamps.
+#[derive(Clone, Debug)]
+pub struct PlaceholderNow {
+    micros: bool,
+    last: Option<Arc<AtomicU64>>, // shared by all copies of the command
+}
+
+impl PlaceholderNow {
+    fn new(micros: bool, monotonic: bool) -> Self {
+        Self { micros, last: monotonic.then(|| Arc::new(AtomicU64::new(0))) }
+    }
+    fn gen_into(&mut self, out: &mut Vec<u8>) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let now = if self.micros { now.as_micros() as u64 } else { now.as_millis() as u64 };
+        let now = match &self.last {
+            Some(last) => {
+                let prev = last.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(now.max(last + 1))).unwrap();
+                now.max(prev + 1)
+            }
+            None => now,
+        };
+        write!(out, "{}", now).unwrap();
+    }
+}
+
+// {choice a b c}, or {choice a b c weights=3,1,1} to pick with probability weight / sum
+#[derive(Clone, Debug)]
+pub struct PlaceholderChoice {
+    items: Vec<Vec<u8>>,
+    choose: WeightedIndex<u64>,
+}
+
+impl PlaceholderChoice {
+    fn new(words: &[&str]) -> Result<Self, String> {
+        let (items, weights) = match words.split_last() {
+            Some((last, items)) if last.starts_with("weights=") => {
+                let weights: Result<Vec<u64>, String> = last["weights=".len()..].split(',').map(|w| w.parse().map_err(|_| format!("invalid weight: {}", w))).collect();
+                (items, weights?)
+            }
+            _ => (words, vec![1; words.len()]),
+        };
+        if items.is_empty() {
+            return Err("no items to choose from".to_string());
+        }
+        if weights.len() != items.len() {
+            return Err(format!("{} weights for {} items", weights.len(), items.len()));
+        }
+        let choose = WeightedIndex::new(weights).map_err(|e| e.to_string())?;
+        Ok(Self { items: items.iter().map(|item| item.as_bytes().to_vec()).collect(), choose })
+    }
+    fn gen_into(&mut self, rng: &mut FastRng, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.items[rng.sample(&self.choose)]);
+    }
+}
+
+// {file words.txt}, a line of the file, uniformly or with a key distribution and its options, e.g. {file words.txt zipfian exponent=1.2}.
+// The file is read once, empty lines are skipped, and the lines are shared by all copies of the command.
+#[derive(Clone, Debug)]
+pub struct PlaceholderFile {
+    lines: Arc<Vec<Vec<u8>>>,
+    distribution: DistributionEnum,
+}
+
+impl PlaceholderFile {
+    fn new(words: &[&str]) -> Result<Self, Invalid> {
+        let path = words[0];
+        let content = std::fs::read(path).map_err(|e| format!("can not read {}: {}", path, e))?;
+        let lines: Vec<Vec<u8>> = content
+            .split(|b| *b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        if lines.is_empty() {
+            return Err(format!("{} has no lines", path).into());
+        }
+        let (name, options) = match words.get(1) {
+            Some(name) if !name.contains('=') => (*name, &words[2..]),
+            _ => ("uniform", &words[1..]),
+        };
+        let distribution = DistributionEnum::new(name, lines.len() as u64, options)?;
+        Ok(Self { lines: Arc::new(lines), distribution })
+    }
+    fn gen_into(&mut self, rng: &mut FastRng, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.lines[self.distribution.sample(rng) as usize]);
+    }
+}
+
+// {uuid}, a random version 4 UUID
+fn gen_uuid(rng: &mut FastRng, out: &mut Vec<u8>) {
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+    for (i, b) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            out.push(b'-');
+        }
+        write!(out, "{:02x}", b).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GenContext, PlaceholderEnum, COMPRESSIBLE_BLOCK};
+    use crate::rng::FastRng;
+
+    fn gen(placeholder: &str) -> String {
+        let mut rng = FastRng::from_entropy();
+        String::from_utf8(PlaceholderEnum::new(placeholder).unwrap().gen(&mut GenContext::new(&mut rng, 0, 0)).remove(0)).unwrap()
+    }
+
+    fn gen_with_key(placeholder: &mut PlaceholderEnum, key: &[u8]) -> Vec<u8> {
+        let mut rng = FastRng::from_entropy();
+        let mut context = GenContext { key: key.to_vec(), ..GenContext::new(&mut rng, 0, 0) };
+        placeholder.gen(&mut context).remove(0)
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(gen("key sequence 100"), "key_0000000000");
+        assert_eq!(gen("key sequence 100 prefix=user: width=0"), "user:0");
+        assert_eq!(gen("key uniform 1 prefix= width=4"), "0000");
+        assert_eq!(gen("key uniform 1 prefix=user: hashtag"), "user:{0000000000}");
+        assert_eq!(gen("key uniform 1 hashtag=bench width=3"), "{bench}key_000");
+
+        let mut key = PlaceholderEnum::new("key sequence 1000 format=hex width=4 prefix=k").unwrap();
+        let keys: Vec<Vec<u8>> = (0..12).map(|_| gen_with_key(&mut key, b"")).collect();
+        assert_eq!(keys[11], b"k000b");
+
+        // distribution options are told apart from key options by name
+        let key = gen("key zipfian 10 exponent=2 prefix=z:");
+        assert!(key.starts_with("z:000000000"), "{}", key);
+        let key = gen("key hotspot 10 hot_keys=0.1 hot_ops=1 width=1");
+        assert_eq!(key, "key_0");
+    }
+
+    #[test]
+    fn test_value() {
+        assert_eq!(gen("value 8").len(), 8);
+        let mut sizes = std::collections::BTreeSet::new();
+        for _ in 0..1000 {
+            let len = gen("value 2-4").len();
+            assert!((2..=4).contains(&len));
+            sizes.insert(len);
+            assert!([1, 100].contains(&gen("value 1:3,100").len()));
+            assert!((10..=20).contains(&gen("value 10-20 gaussian mean=12 stddev=2").len()));
+            assert!((10..=20).contains(&gen("value 10-20 zipfian exponent=2").len()));
+        }
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(gen("value 5:0,7:1").len(), 7);
+        assert!(super::PlaceholderValue::new(&["8-4"]).is_err());
+        assert!(super::PlaceholderValue::new(&["8", "zipfian"]).is_err());
+        assert!(super::PlaceholderValue::new(&["1:0,2:0"]).is_err());
+    }
+
+    #[test]
+    fn test_value_content() {
+        assert!(gen("value 100").bytes().all(|b| b.is_ascii_alphanumeric()));
+        assert_eq!(gen("value 5 content=fixed"), "xxxxx");
+        for _ in 0..100 {
+            let n = gen("value 1-18 content=numeric");
+            assert!(n.bytes().all(|b| b.is_ascii_digit()) && (n == "0" || !n.starts_with('0')), "{}", n);
+            assert!(n.parse::<i64>().is_ok());
+        }
+        let mut bytes = PlaceholderEnum::new("value 10000 content=bytes").unwrap();
+        assert!(gen_with_key(&mut bytes, b"").iter().any(|b| !b.is_ascii()));
+
+        // a quarter of the value is unique, the rest repeats it
+        let value = gen("value 100 content=compressible ratio=0.25");
+        assert_eq!(value.len(), 100);
+        assert_eq!(value[..25], value[25..50]);
+        assert_eq!(value[..25], value[75..]);
+        // large values repeat within each block, the blocks differ
+        let value = gen("value 1000000 content=compressible ratio=0.25");
+        assert_eq!(value.len(), 1_000_000);
+        for (i, block) in value.as_bytes().chunks(COMPRESSIBLE_BLOCK).enumerate() {
+            let unique = (block.len() as f64 * 0.25).ceil() as usize;
+            assert!(block.chunks(unique).all(|repeat| *repeat == block[..repeat.len()]), "block {}", i);
+        }
+        assert_ne!(value[..COMPRESSIBLE_BLOCK], value[COMPRESSIBLE_BLOCK..2 * COMPRESSIBLE_BLOCK]);
+
+        // the same key gets the same size and content, with any copy of the placeholder
+        let mut a = PlaceholderEnum::new("value 10-1000 content=derived").unwrap();
+        let mut b = a.clone();
+        assert!(a.is_derived());
+        assert_eq!(gen_with_key(&mut a, b"key_1"), gen_with_key(&mut b, b"key_1"));
+        assert_eq!(gen_with_key(&mut a, b"key_2"), gen_with_key(&mut a, b"key_2"));
+        assert_ne!(gen_with_key(&mut a, b"key_1"), gen_with_key(&mut a, b"key_2"));
+
+        assert!(super::PlaceholderValue::new(&["8", "content=zeros"]).is_err());
+        assert!(super::PlaceholderValue::new(&["8", "ratio=0.5"]).is_err());
+        assert!(super::PlaceholderValue::new(&["8", "content=compressible", "ratio=0"]).is_err());
+        assert!(super::PlaceholderValue::new(&["content=fixed"]).is_err());
+        assert!(super::PlaceholderValue::new(&["8-16", "zipfian", "content=fixed", "exponent=2"]).is_ok());
+    }
+
+    #[test]
+    fn test_named() {
+        let mut rng = FastRng::from_entropy();
+        let mut context = GenContext::new(&mut rng, 0, 0);
+        let mut key = PlaceholderEnum::new("key:k1 uniform 1000 width=0").unwrap();
+        let mut range = PlaceholderEnum::new("range:r 100 5").unwrap();
+        assert_eq!(key.name(), Some("k1"));
+        assert!(key.is_key() && range.is_multi_arg());
+        let k1 = key.gen(&mut context);
+        let r = range.gen(&mut context);
+        assert_eq!(PlaceholderEnum::new("ref k1").unwrap().gen(&mut context), k1);
+        assert_eq!(PlaceholderEnum::new("ref r").unwrap().gen(&mut context), r);
+        assert_eq!(PlaceholderEnum::new("ref k1").unwrap().ref_name(), Some("k1"));
+    }
+
+    #[test]
+    fn test_misc() {
+        for _ in 0..100 {
+            let x: f64 = gen("float -1.5 2.5").parse().unwrap();
+            assert!((-1.5..2.5).contains(&x));
+            assert!(["a", "b"].contains(&gen("choice a b").as_str()));
+            assert_eq!(gen("choice a b c weights=0,1,0"), "b");
+        }
+        assert_eq!(gen("float 3 3"), "3");
+
+        let now: u64 = gen("now_ms").parse().unwrap();
+        let now_us: u64 = gen("now_us").parse().unwrap();
+        assert!(now > 1_600_000_000_000 && now_us / 1000 >= now);
+        let mut now = PlaceholderEnum::new("now_ms monotonic").unwrap();
+        let mut copy = now.clone();
+        let mut rng = FastRng::from_entropy();
+        let mut context = GenContext::new(&mut rng, 0, 0);
+        let times: Vec<u64> = (0..100).map(|i| {
+            let ph = if i % 2 == 0 { &mut now } else { &mut copy };
+            String::from_utf8(ph.gen(&mut context).remove(0)).unwrap().parse().unwrap()
+        }).collect();
+        assert!(times.windows(2).all(|w| w[0] < w[1]), "{:?}", times);
+
+        let uuid = gen("uuid");
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(uuid.split('-').map(str::len).collect::<Vec<_>>(), vec![8, 4, 4, 4, 12]);
+        assert_eq!(&uuid[14..15], "4");
+        assert_ne!(gen("uuid"), uuid);
+
+        let mut rng = FastRng::from_entropy();
+        let mut context = GenContext::new(&mut rng, 7, 2);
+        assert_eq!(PlaceholderEnum::new("conn_id").unwrap().gen(&mut context), vec![b"7".to_vec()]);
+        assert_eq!(PlaceholderEnum::new("thread_id").unwrap().gen(&mut context), vec![b"2".to_vec()]);
+
+        assert!(super::PlaceholderFloat::new("2", "1").is_err());
+        assert!(super::PlaceholderFloat::new("-1e308", "1e308").is_err());
+        assert!(super::PlaceholderFloat::new("-1e307", "1e307").is_ok());
+        assert!(super::PlaceholderChoice::new(&["a", "weights=1,2"]).is_err());
+        assert!(super::PlaceholderChoice::new(&[]).is_err());
+    }
+
+    #[test]
+    fn test_file() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/words.txt");
+        let mut file = PlaceholderEnum::new(&format!("file {} sequence", path)).unwrap();
+        let words: Vec<Vec<u8>> = (0..7).map(|_| gen_with_key(&mut file, b"")).collect();
+        assert_eq!(words, ["apple", "banana", "cherry", "date", "elderberry", "apple", "banana"].map(|w| w.as_bytes().to_vec()));
+        for _ in 0..100 {
+            assert!(["apple", "banana"].contains(&gen(&format!("file {} hotspot hot_keys=0.4 hot_ops=1", path)).as_str()));
+            assert!(!gen(&format!("file {}", path)).is_empty());
+        }
+        assert!(super::PlaceholderFile::new(&["/nonexistent/words.txt"]).is_err());
+        assert!(super::PlaceholderFile::new(&[path, "pareto"]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::io;
+use std::time::Duration;
+
+use redis::{ErrorKind, RedisError, RedisResult};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::command::write_bulk;
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// A connection to a standalone server that sends pipelines encoded by Command::encode as they are, and only checks
+// the replies for errors instead of parsing them into values.
+pub struct RespConnection {
+    stream: Box<dyn Stream>,
+    buf: Vec<u8>, // read from the stream, the replies before start are consumed
+    start: usize,
+    scan: ReplyScan, // of the reply at start
+    timeout: Duration,
+    pending: u64, // replies not read yet, including the late ones of timed out pipelines
+    broken: bool, // a pipeline timed out while it was being written
+}
+
+impl RespConnection {
+    pub async fn new(stream: Box<dyn Stream>, username: &str, password: &str, timeout: Duration) -> RedisResult<Self> {
+        let mut conn = RespConnection { stream, buf: Vec::with_capacity(16 * 1024), start: 0, scan: ReplyScan::default(), timeout, pending: 0, broken: false };
+        if !password.is_empty() {
+            let mut auth = Vec::new();
+            let args: &[&str] = if username.is_empty() { &["AUTH", password] } else { &["AUTH", username, password] };
+            auth.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+            args.iter().for_each(|arg| write_bulk(&mut auth, arg.as_bytes()));
+            if let Some((_, e)) = conn.run(&auth, 1).await?.pop() {
+                return Err(e);
+            }
+        }
+        Ok(conn)
+    }
+
+    // Sends count requests and returns the error replies among theirs, by index. Fails as a whole when the connection
+    // does or the replies time out.
+    pub async fn run(&mut self, request: &[u8], count: u64) -> RedisResult<Vec<(usize, RedisError)>> {
+        if self.broken {
+            return Err(io::Error::from(io::ErrorKind::ConnectionAborted).into());
+        }
+        match tokio::time::timeout(self.timeout, self.send_recv(request, count)).await {
+            Ok(result) => result,
+            Err(elapsed) => Err(io::Error::from(elapsed).into()),
+        }
+    }
+
+    async fn send_recv(&mut self, request: &[u8], count: u64) -> RedisResult<Vec<(usize, RedisError)>> {
+        self.broken = true;
+        self.stream.write_all(request).await?;
+        self.broken = false;
+        self.pending += count;
+        let mut errors = Vec::new();
+        while self.pending > 0 {
+            let reply = self.read_reply().await?;
+            self.pending -= 1;
+            // replies of earlier pipelines that timed out are skipped
+            if self.pending < count && matches!(self.buf[reply.start], b'-' | b'!') {
+                if let Err(e) = redis::parse_redis_value(&self.buf[reply])?.extract_error() {
+                    errors.push(((count - 1 - self.pending) as usize, e));
+                }
+            }
+        }
+        Ok(errors)
+    }
+
+    // Safe to cancel, the bytes read and scanned so far stay in buf and scan.
+    async fn read_reply(&mut self) -> RedisResult<std::ops::Range<usize>> {
+        loop {
+            if let Some(len) = self.scan.next(&self.buf[self.start..])? {
+                self.start += len;
+                return Ok(self.start - len..self.start);
+            }
+            self.buf.drain(..self.start);
+            self.start = 0;
+            self.buf.reserve(16 * 1024);
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+}
+
+// Finds where a reply ends while it arrives in pieces. The values still missing from the reply and the end of those
+// scanned are kept across calls, so every byte of a large aggregate reply is scanned once.
+#[derive(Default)]
+struct ReplyScan {
+    len: usize,     // of the values scanned so far
+    missing: u64,   // values to scan, 0 before the reply starts
+}
+
+impl ReplyScan {
+    // The length of the first reply in buf, None while it is incomplete. buf has to start with the same bytes on every
+    // call until the reply is complete.
+    fn next(&mut self, buf: &[u8]) -> RedisResult<Option<usize>> {
+        if self.missing == 0 {
+            self.missing = 1;
+        }
+        while self.missing > 0 {
+            let Some((len, children)) = value_len(&buf[self.len..])? else {
+                return Ok(None);
+            };
+            self.len += len;
+            self.missing += children;
+            self.missing -= 1;
+        }
+        Ok(Some(std::mem::take(&mut self.len)))
+    }
+}
+
+// The length of the first value in buf without the elements of an aggregate, and the number of those elements. None
+// while it is incomplete.
+fn value_len(buf: &[u8]) -> RedisResult<Option<(usize, u64)>> {
+    let Some(line_end) = buf.windows(2).position(|w| w == b"\r\n") else {
+        return Ok(None);
+    };
+    let invalid = || RedisError::from((ErrorKind::ParseError, "invalid reply", String::from_utf8_lossy(&buf[..line_end]).to_string()));
+    let number = || std::str::from_utf8(&buf[1..line_end]).ok().and_then(|s| s.parse::<i64>().ok()).ok_or_else(invalid);
+    let mut len = line_end + 2;
+    let mut children = 0;
+    match buf[0] {
+        b'+' | b'-' | b':' | b',' | b'#' | b'_' | b'(' => {}
+        b'$' | b'!' | b'=' => {
+            let n = number()?;
+            if n >= 0 {
+                len += n as usize + 2;
+                if buf.len() < len {
+                    return Ok(None);
+                }
+            }
+        }
+        b'*' | b'~' | b'>' | b'%' => {
+            let n = number()?.max(0) as u64;
+            children = if buf[0] == b'%' { n * 2 } else { n };
+        }
+        _ => return Err(invalid()),
+    }
+    Ok(Some((len, children)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplyScan, RespConnection};
+    use redis::RedisResult;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    fn reply_len(buf: &[u8]) -> RedisResult<Option<usize>> {
+        ReplyScan::default().next(buf)
+    }
+
+    #[test]
+    fn test_reply_len() {
+        for reply in ["+OK\r\n", "-ERR x\r\n", ":1\r\n", "$-1\r\n", "$3\r\na\r\n\r\n", "*-1\r\n", "*0\r\n", "*2\r\n$1\r\na\r\n*1\r\n:1\r\n", "%1\r\n+a\r\n:1\r\n"] {
+            let mut buf = reply.as_bytes().to_vec();
+            assert_eq!(reply_len(&buf).unwrap(), Some(reply.len()), "{:?}", reply);
+            buf.extend_from_slice(b"+next\r\n");
+            assert_eq!(reply_len(&buf).unwrap(), Some(reply.len()), "{:?}", reply);
+            for end in 0..reply.len() {
+                assert_eq!(reply_len(&buf[..end]).unwrap(), None, "{:?}", &reply[..end]);
+            }
+        }
+        assert!(reply_len(b"?\r\n").is_err());
+        assert!(reply_len(b"$x\r\n").is_err());
+    }
+
+    #[test]
+    fn test_reply_scan() {
+        // a large nested reply arriving in small pieces, each piece is scanned from where the last one stopped
+        let mut reply = b"*100000\r\n".to_vec();
+        for i in 0..100_000 {
+            reply.extend_from_slice(format!("*2\r\n$5\r\nfield\r\n:{}\r\n", i).as_bytes());
+        }
+        let mut buf = reply.clone();
+        buf.extend_from_slice(b"+next\r\n");
+        let mut scan = ReplyScan::default();
+        let mut scanned = 0;
+        for end in (7..reply.len()).step_by(7) {
+            assert_eq!(scan.next(&buf[..end]).unwrap(), None);
+            assert!(scan.len >= scanned && scan.len <= end);
+            scanned = scan.len;
+        }
+        assert_eq!(scan.next(&buf).unwrap(), Some(reply.len()));
+        assert_eq!(scan.next(&buf[reply.len()..]).unwrap(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut conn = RespConnection::new(Box::new(client), "", "", Duration::from_millis(100)).await.unwrap();
+
+        // each error reply of the pipeline
+        server.write_all(b"+OK\r\n-WRONGTYPE x\r\n-ERR y\r\n").await.unwrap();
+        let errors = conn.run(b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n", 3).await.unwrap();
+        assert_eq!(errors.iter().map(|(i, e)| (*i, e.code().unwrap())).collect::<Vec<_>>(), vec![(1, "WRONGTYPE"), (2, "ERR")]);
+
+        // the late reply of a timed out pipeline is not taken for the next one
+        assert!(conn.run(b"*1\r\n$4\r\nPING\r\n", 1).await.unwrap_err().is_timeout());
+        server.write_all(b"-ERR late\r\n+OK\r\n").await.unwrap();
+        assert!(conn.run(b"*1\r\n$4\r\nPING\r\n", 1).await.unwrap().is_empty());
+
+        drop(server);
+        assert!(conn.run(b"*1\r\n$4\r\nPING\r\n", 1).await.unwrap_err().is_unrecoverable_error());
+    }
+}
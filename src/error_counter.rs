@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use redis::RedisError;
+
+pub struct ErrorCounter {
+    cnt: AtomicU64,
+    kinds: Mutex<BTreeMap<String, u64>>,
+}
+
+impl ErrorCounter {
+    pub fn new() -> ErrorCounter {
+        ErrorCounter {
+            cnt: AtomicU64::new(0),
+            kinds: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Server errors are keyed by the reply prefix (ERR, WRONGTYPE, MOVED...), client side errors by lowercase names.
+    pub fn kind(e: &RedisError) -> String {
+        if let Some(code) = e.code() {
+            code.to_string()
+        } else if e.is_timeout() {
+            "timeout".to_string()
+        } else if e.is_connection_refusal() {
+            "refused".to_string()
+        } else if e.is_io_error() || e.is_connection_dropped() {
+            "io".to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+
+    pub fn record(&self, e: &RedisError, cnt: u64) {
+        let mut kinds = self.kinds.lock().unwrap();
+        self.cnt.fetch_add(cnt, Ordering::Relaxed);
+        *kinds.entry(Self::kind(e)).or_insert(0) += cnt;
+    }
+
+    // Drops the errors so far along with their kinds, like Histogram::clear.
+    pub fn clear(&self) {
+        let mut kinds = self.kinds.lock().unwrap();
+        self.cnt.store(0, Ordering::Relaxed);
+        kinds.clear();
+    }
+
+    pub fn cnt(&self) -> u64 {
+        self.cnt.load(Ordering::Relaxed)
+    }
+
+    pub fn kinds(&self) -> BTreeMap<String, u64> {
+        self.kinds.lock().unwrap().clone()
+    }
+}
+
+impl Display for ErrorCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kinds = self.kinds.lock().unwrap();
+        let kinds: Vec<String> = kinds.iter().map(|(kind, cnt)| format!("{}: {}", kind, cnt)).collect();
+        write!(f, "errors: {} ({})", self.cnt(), kinds.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorCounter;
+    use redis::{ErrorKind, RedisError};
+
+    #[test]
+    fn test() {
+        let counter = ErrorCounter::new();
+        let reply = |s: &[u8]| redis::parse_redis_value(s).unwrap().extract_error().unwrap_err();
+        counter.record(&reply(b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"), 1);
+        counter.record(&reply(b"-ERR unknown command\r\n"), 2);
+        counter.record(&RedisError::from(std::io::Error::from(std::io::ErrorKind::TimedOut)), 3);
+        counter.record(&RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused)), 4);
+        counter.record(&RedisError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe)), 5);
+        counter.record(&RedisError::from((ErrorKind::TypeError, "Response was of incompatible type")), 6);
+        println!("{}", counter);
+
+        assert_eq!(counter.cnt(), 21);
+        let kinds = counter.kinds();
+        assert_eq!(kinds["WRONGTYPE"], 1);
+        assert_eq!(kinds["ERR"], 2);
+        assert_eq!(kinds["timeout"], 3);
+        assert_eq!(kinds["refused"], 4);
+        assert_eq!(kinds["io"], 5);
+        assert_eq!(kinds["other"], 6);
+
+        counter.clear();
+        assert_eq!(counter.cnt(), 0);
+        assert!(counter.kinds().is_empty());
+    }
+}
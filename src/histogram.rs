@@ -1,74 +1,93 @@
 use std::fmt::Display;
 use std::sync::atomic::{AtomicU64, Ordering};
 
+pub const DEFAULT_SIGNIFICANT_DIGITS: u8 = 3;
+pub const DEFAULT_HIGHEST_NS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+
+// HDR-style log-linear histogram of latencies in ns.
+// Bucket 0 covers [0, 2 * half) with a step of 1ns, bucket k covers [half << k, half << (k + 1)) with a step of 1 << k,
+// so every value is kept within 1 / half of its magnitude, where half >= 10 ^ significant_digits.
 pub struct Histogram {
     cnt: AtomicU64,
+    sum: AtomicU64, // exact sum of latencies, buckets only keep the lower bound
+    min: AtomicU64,
+    max: AtomicU64,
+
+    half_magnitude: u32, // log2(half)
+    highest_ns: u64,     // larger values are counted at this value
     buckets: Vec<AtomicU64>,
 }
 
 impl Histogram {
-    // PRECISION:
-    // 0-99: 10us <1ms
-    // 100-199: 100us <10ms
-    // 200-299: 1ms <100ms
-    // 300-399: 10ms <1s
-    // 400-499: 100ms <10s
-    // 500: >=10s
-    pub fn new() -> Histogram {
-        let mut buckets = Vec::with_capacity(501);
-        for _ in 0..501 {
-            buckets.push(AtomicU64::new(0));
+    pub fn new(significant_digits: u8, highest_ns: u64) -> Histogram {
+        assert!((1..=5).contains(&significant_digits), "significant digits must be between 1 and 5");
+        let largest_single_unit = 2 * 10u64.pow(significant_digits as u32);
+        let half_magnitude = (largest_single_unit as f64).log2().ceil() as u32 - 1;
+        let sub_bucket_count = 1u64 << (half_magnitude + 1);
+
+        let mut bucket_count = 1;
+        let mut smallest_untrackable = sub_bucket_count;
+        while smallest_untrackable <= highest_ns && smallest_untrackable < (1 << 63) {
+            smallest_untrackable <<= 1;
+            bucket_count += 1;
         }
+        let len = (bucket_count + 1) << half_magnitude;
         Histogram {
             cnt: AtomicU64::new(0),
-            buckets,
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+            half_magnitude,
+            highest_ns,
+            buckets: (0..len).map(|_| AtomicU64::new(0)).collect(),
         }
     }
 
-    pub fn record(&self, latency_us: u64) {
-        let index = match latency_us {
-            0..=999 => latency_us / 10,                            // <1ms precision 10us
-            1_000..=9_999 => 100 + (latency_us / 100),             // <10ms precision 100us
-            10_000..=99_999 => 200 + (latency_us / 1_000),         // <100ms precision 1ms
-            100_000..=999_999 => 300 + (latency_us / 10_000),      // <1s precision 10ms
-            1_000_000..=9_999_999 => 400 + (latency_us / 100_000), // <10s precision 100ms
-            _ => 500,                                              // >=10s
-        };
+    fn index(&self, latency_ns: u64) -> usize {
+        let half = 1u64 << self.half_magnitude;
+        let sub_bucket_mask = (half << 1) - 1;
+        let bucket = 64 - (latency_ns | sub_bucket_mask).leading_zeros() - (self.half_magnitude + 1);
+        let sub_bucket = latency_ns >> bucket;
+        (((bucket as u64 + 1) << self.half_magnitude) + sub_bucket - half) as usize
+    }
+
+    fn bucket_unit_ns(&self, index: usize) -> u64 {
+        let half = 1u64 << self.half_magnitude;
+        let index = index as u64;
+        let bucket = (index >> self.half_magnitude) as i64 - 1;
+        let sub_bucket = (index & (half - 1)) + half;
+        if bucket < 0 {
+            sub_bucket - half
+        } else {
+            sub_bucket << bucket
+        }
+    }
+
+    pub fn record(&self, latency_ns: u64) {
+        let index = self.index(latency_ns.min(self.highest_ns));
         self.cnt.fetch_add(1, Ordering::Relaxed);
-        self.buckets[index as usize].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(latency_ns, Ordering::Relaxed);
+        self.min.fetch_min(latency_ns, Ordering::Relaxed);
+        self.max.fetch_max(latency_ns, Ordering::Relaxed);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
     }
 
-    #[allow(dead_code)]
     pub fn clear(&self) {
         self.cnt.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.min.store(u64::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
         for i in 0..self.buckets.len() {
             self.buckets[i].store(0, Ordering::Relaxed);
         }
     }
 
     #[allow(dead_code)]
-    pub fn un_record(&self, latency_us: u64) {
-        let index = match latency_us {
-            0..=999 => latency_us / 10,                            // <1ms precision 10us
-            1_000..=9_999 => 100 + (latency_us / 100),             // <10ms precision 100us
-            10_000..=99_999 => 200 + (latency_us / 1_000),         // <100ms precision 1ms
-            100_000..=999_999 => 300 + (latency_us / 10_000),      // <1s precision 10ms
-            1_000_000..=9_999_999 => 400 + (latency_us / 100_000), // <10s precision 100ms
-            _ => 500,                                              // >=10s
-        };
+    pub fn un_record(&self, latency_ns: u64) {
+        let index = self.index(latency_ns.min(self.highest_ns));
         self.cnt.fetch_sub(1, Ordering::Relaxed);
-        self.buckets[index as usize].fetch_sub(1, Ordering::Relaxed);
-    }
-
-    fn bucket_unit_us(index: u64) -> u64 {
-        match index {
-            0..=99 => index * 10,                 // precision 10us
-            100..=199 => (index - 100) * 100,     // precision 100us
-            200..=299 => (index - 200) * 1_000,   // precision 1ms
-            300..=399 => (index - 300) * 10_000,  // precision 10ms
-            400..=499 => (index - 400) * 100_000, // precision 100ms
-            _ => 10_000_000,                      // >=10s
-        }
+        self.sum.fetch_sub(latency_ns, Ordering::Relaxed); // min and max are kept
+        self.buckets[index].fetch_sub(1, Ordering::Relaxed);
     }
 
     pub fn cnt(&self) -> u64 {
@@ -80,11 +99,36 @@ impl Histogram {
         if cnt == 0 {
             return 0;
         }
-        let mut sum = 0;
+        self.sum.load(Ordering::Relaxed) / cnt
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.cnt() == 0 {
+            return 0;
+        }
+        self.min.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    // Squares are not tracked, so each sample is taken at its bucket's lower bound around the exact mean.
+    pub fn stddev(&self) -> f64 {
+        let cnt = self.cnt();
+        if cnt == 0 {
+            return 0.0;
+        }
+        let avg = self.sum.load(Ordering::Relaxed) as f64 / cnt as f64;
+        let mut variance = 0.0;
         for i in 0..self.buckets.len() {
-            sum += Histogram::bucket_unit_us(i as u64) * self.buckets[i].load(Ordering::Relaxed);
+            let n = self.buckets[i].load(Ordering::Relaxed);
+            if n != 0 {
+                let diff = self.bucket_unit_ns(i) as f64 - avg;
+                variance += diff * diff * n as f64;
+            }
         }
-        sum / cnt
+        (variance / cnt as f64).sqrt()
     }
 
     pub fn percentile(&self, percentile: f64) -> u64 {
@@ -93,29 +137,43 @@ impl Histogram {
             return 0;
         }
         let mut sum = 0;
+        let mut last = 0;
         let target = (cnt as f64 * percentile) as u64;
         for i in 0..self.buckets.len() {
-            sum += self.buckets[i].load(Ordering::Relaxed);
+            let n = self.buckets[i].load(Ordering::Relaxed);
+            sum += n;
             if sum > 0 && sum >= target {
-                return Histogram::bucket_unit_us(i as u64);
+                return self.bucket_unit_ns(i);
+            }
+            if n != 0 {
+                last = i;
             }
         }
-        0
-    }
-
-    fn humanize_us(latency_us: u64) -> String {
-        match latency_us {
-            0 => "<0.01ms".to_string(),
-            1..=999 => format!("{:.2}ms", latency_us as f64 / 1_000.0),
-            1_000..=9_999 => format!("{:.1}ms", latency_us as f64 / 1_000.0),
-            10_000..=99_999 => format!("{:.0}ms", latency_us as f64 / 1_000.0),
-            100_000..=999_999 => format!("{:.2}s", latency_us as f64 / 1_000_000.0),
-            1_000_000..=9_999_999 => format!("{:.1}s", latency_us as f64 / 1_000_000.0),
-            _ => ">10s".to_string(),
+        // cnt may run ahead of the buckets when recorded concurrently with a clear
+        self.bucket_unit_ns(last)
+    }
+
+    pub fn humanize_ns(latency_ns: u64) -> String {
+        match latency_ns {
+            0..=999 => format!("{}ns", latency_ns),
+            1_000..=9_999 => format!("{:.2}us", latency_ns as f64 / 1_000.0),
+            10_000..=99_999 => format!("{:.1}us", latency_ns as f64 / 1_000.0),
+            100_000..=999_999 => format!("{:.0}us", latency_ns as f64 / 1_000.0),
+            1_000_000..=9_999_999 => format!("{:.1}ms", latency_ns as f64 / 1_000_000.0),
+            10_000_000..=99_999_999 => format!("{:.0}ms", latency_ns as f64 / 1_000_000.0),
+            100_000_000..=999_999_999 => format!("{:.2}s", latency_ns as f64 / 1_000_000_000.0),
+            1_000_000_000..=9_999_999_999 => format!("{:.1}s", latency_ns as f64 / 1_000_000_000.0),
+            _ => format!("{:.0}s", latency_ns as f64 / 1_000_000_000.0),
         }
     }
 }
 
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new(DEFAULT_SIGNIFICANT_DIGITS, DEFAULT_HIGHEST_NS)
+    }
+}
+
 impl Display for Histogram {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let cnt = self.cnt();
@@ -125,7 +183,7 @@ impl Display for Histogram {
         let avg = self.avg();
         let p99 = self.percentile(0.99);
 
-        write!(f, "cnt: {}, avg: {}, p99: {}", cnt, Histogram::humanize_us(avg), Histogram::humanize_us(p99))
+        write!(f, "cnt: {}, avg: {}, p99: {}", cnt, Histogram::humanize_ns(avg), Histogram::humanize_ns(p99))
     }
 }
 
@@ -135,36 +193,56 @@ mod tests {
 
     #[test]
     fn test() {
-        let histogram = Histogram::new();
+        let histogram = Histogram::default();
         for i in 0..1000 {
-            histogram.record(i * 1000);
+            histogram.record(i * 1_000_000);
         }
         println!("{}", histogram);
 
-        // PRECISION:
-        // 0-99: 10us <1ms
-        // 100-199: 100us <10ms
-        // 200-299: 1ms <100ms
-        // 300-399: 10ms <1s
-        // 400-499: 100ms <10s
-        // 500: >=10s
-        let src = [0, 9, 10, 99, 100, 999, 1_000, 9_999, 10_000, 99_999, 100_000, 999_999, 1_000_000, 9_999_999, 10_000_000, 99_999_999, 100_000_000];
-        let dst = [0, 0, 10, 90, 100, 990, 1_000, 9_900, 10_000, 99_000, 100_000, 990_000, 1_000_000, 9_900_000, 10_000_000, 10_000_000, 10_000_000];
-        for i in 0..src.len() {
-            let histogram = Histogram::new();
+        // 3 significant digits: exact below 2048ns, then within 1/1024 of the value
+        for src in [0, 1, 9, 2_047, 3_200, 9_999, 10_000, 999_999, 1_000_000, 99_999_999, 9_999_999_999, 300_000_000_000] {
+            let histogram = Histogram::default();
             for _ in 0..1000 {
-                histogram.record(src[i]);
+                histogram.record(src);
+            }
+            let dst = histogram.percentile(0.5);
+            println!("src: {} dst: {} {}", src, dst, histogram);
+            assert!(dst <= src && src - dst <= src / 1024, "src: {} dst: {}", src, dst);
+            if src < 4_096 {
+                assert_eq!(dst, src / 2 * 2 + if src < 2_048 { src % 2 } else { 0 });
             }
-
-            println!("src: {} dst: {}", src[i], dst[i]);
-            println!("{}", histogram);
             assert_eq!(histogram.cnt(), 1000);
-            assert_eq!(histogram.avg(), dst[i]);
-            assert_eq!(histogram.percentile(0.0), dst[i]);
-            assert_eq!(histogram.percentile(0.5), dst[i]);
-            assert_eq!(histogram.percentile(0.99), dst[i]);
-            assert_eq!(histogram.percentile(0.999), dst[i]);
-            assert_eq!(histogram.percentile(1.0), dst[i]);
+            assert_eq!(histogram.avg(), src);
+            assert_eq!(histogram.min(), src);
+            assert_eq!(histogram.max(), src);
+            assert_eq!(histogram.percentile(0.0), dst);
+            assert_eq!(histogram.percentile(0.99), dst);
+            assert_eq!(histogram.percentile(0.999), dst);
+            assert_eq!(histogram.percentile(1.0), dst);
+        }
+
+        // values above the upper bound are counted at the bound, min and max stay exact
+        let histogram = Histogram::new(2, 1_000_000);
+        histogram.record(5_000_000);
+        assert!(histogram.percentile(1.0) <= 1_000_000 && histogram.percentile(1.0) >= 990_000);
+        assert_eq!(histogram.max(), 5_000_000);
+
+        // more digits, finer buckets
+        let histogram = Histogram::new(5, 1_000_000_000);
+        histogram.record(123_456_789);
+        assert!(123_456_789 - histogram.percentile(1.0) <= 123_456_789 / 100_000);
+
+        let histogram = Histogram::default();
+        for latency_ns in [100_000, 200_000, 300_000, 400_000, 5_000_000] {
+            histogram.record(latency_ns);
         }
+        assert_eq!(histogram.avg(), 1_200_000);
+        assert_eq!(histogram.min(), 100_000);
+        assert_eq!(histogram.max(), 5_000_000);
+        assert!((histogram.stddev() - 1_902_630.0).abs() < 2_000.0, "stddev: {}", histogram.stddev());
+        assert_eq!(histogram.percentile(0.5), 199_936);
+        histogram.clear();
+        assert_eq!((histogram.cnt(), histogram.avg(), histogram.min(), histogram.max()), (0, 0, 0, 0));
+        assert_eq!(histogram.stddev(), 0.0);
     }
 }
@@ -1,75 +1,296 @@
 mod bench;
 mod client;
+mod connection;
 mod command;
 mod auto_connection;
 mod shared_context;
 mod histogram;
 mod async_flag;
+mod tls;
+mod error_counter;
+mod output;
+mod rng;
 
-use ctrlc;
+use std::collections::HashMap;
+use pyo3::exceptions::{PyKeyboardInterrupt, PyValueError};
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
-use crate::command::Command;
+use crate::command::{Command, ParseError};
+use crate::histogram::Histogram;
+
+pyo3::create_exception!(_resp_benchmark_rust_lib, TemplateError, PyValueError, "An invalid command template, with the byte offset of the offending part and what was expected there.");
 
 /// A Python module implemented in Rust.
 #[pymodule]
 fn _resp_benchmark_rust_lib(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(benchmark, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::benchmark, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::self_benchmark, m)?)?;
+    m.add("TemplateError", m.py().get_type_bound::<TemplateError>())?;
     Ok(())
 }
 
+// A TemplateError with the offset and expected attributes, the message is the ParseError with its caret.
+fn template_error(py: Python<'_>, e: ParseError) -> PyErr {
+    let err = TemplateError::new_err(e.to_string());
+    let value = err.value_bound(py);
+    value.setattr("offset", e.offset).and_then(|_| value.setattr("expected", e.expected)).expect("exceptions take attributes");
+    err
+}
+
+
+#[pyclass]
+#[derive(Clone, Default)]
+struct CommandResult {
+    #[pyo3(get, set)] pub command: String,
+    #[pyo3(get, set)] pub weight: u64,
+    #[pyo3(get, set)] pub qps: f64,
+    #[pyo3(get, set)] pub avg_value_size: f64,
+    #[pyo3(get, set)] pub avg_latency_ms: f64,
+    #[pyo3(get, set)] pub stddev_latency_ms: f64,
+    #[pyo3(get, set)] pub min_latency_ms: f64,
+    #[pyo3(get, set)] pub p50_latency_ms: f64,
+    #[pyo3(get, set)] pub p90_latency_ms: f64,
+    #[pyo3(get, set)] pub p95_latency_ms: f64,
+    #[pyo3(get, set)] pub p99_latency_ms: f64,
+    #[pyo3(get, set)] pub p999_latency_ms: f64,
+    #[pyo3(get, set)] pub p9999_latency_ms: f64,
+    #[pyo3(get, set)] pub max_latency_ms: f64,
+}
+
+#[pyclass]
+#[derive(Clone, Default)]
+struct Sample {
+    #[pyo3(get, set)] pub timestamp: f64, // unix time in seconds at the end of the interval
+    #[pyo3(get, set)] pub ops: u64,
+    #[pyo3(get, set)] pub qps: f64,
+    #[pyo3(get, set)] pub errors: u64,
+    #[pyo3(get, set)] pub p50_latency_ms: f64,
+    #[pyo3(get, set)] pub p99_latency_ms: f64,
+    #[pyo3(get, set)] pub max_latency_ms: f64,
+    #[pyo3(get, set)] pub connections: u64,
+}
+
+impl Sample {
+    fn new(histogram: &Histogram, seconds: f64, errors: u64, connections: u64) -> Self {
+        let ms = |ns: u64| ns as f64 / 1_000_000.0;
+        Sample {
+            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64(),
+            ops: histogram.cnt(),
+            qps: histogram.cnt() as f64 / seconds,
+            errors,
+            p50_latency_ms: ms(histogram.percentile(0.5)),
+            p99_latency_ms: ms(histogram.percentile(0.99)),
+            max_latency_ms: ms(histogram.max()),
+            connections,
+        }
+    }
+}
 
 #[pyclass]
 #[derive(Default)]
 struct BenchmarkResult {
     #[pyo3(get, set)] pub qps: f64,
+    #[pyo3(get, set)] pub avg_value_size: f64,
     #[pyo3(get, set)] pub avg_latency_ms: f64,
+    #[pyo3(get, set)] pub stddev_latency_ms: f64,
+    #[pyo3(get, set)] pub min_latency_ms: f64,
+    #[pyo3(get, set)] pub p50_latency_ms: f64,
+    #[pyo3(get, set)] pub p90_latency_ms: f64,
+    #[pyo3(get, set)] pub p95_latency_ms: f64,
     #[pyo3(get, set)] pub p99_latency_ms: f64,
+    #[pyo3(get, set)] pub p999_latency_ms: f64,
+    #[pyo3(get, set)] pub p9999_latency_ms: f64,
+    #[pyo3(get, set)] pub max_latency_ms: f64,
     #[pyo3(get, set)] pub connections: u64,
+    #[pyo3(get, set)] pub rate: f64,
+    #[pyo3(get, set)] pub errors: u64,
+    #[pyo3(get, set)] pub error_kinds: HashMap<String, u64>,
+    #[pyo3(get, set)] pub commands: Vec<CommandResult>,
+    #[pyo3(get, set)] pub samples: Vec<Sample>,
+    #[pyo3(get, set)] pub interrupted: bool,
 }
 
-#[pyfunction]
-fn benchmark(
-    host: String,
-    port: u16,
-    username: String,
-    password: String,
-    cluster: bool,
-    tls: bool,
-    timeout: u64,
-    cores: Vec<u16>,
-    command: String,
-    connections: u64,
-    pipeline: u64,
-    count: u64,
-    seconds: u64,
-    load: bool,
-    quiet: bool,
-) -> PyResult<BenchmarkResult> {
-    assert!(cores.len() > 0);
-    if load {
-        assert_ne!(count, 0, "count must be greater than 0");
-    }
+macro_rules! impl_set_latency {
+    ($t:ty) => {
+        impl $t {
+            fn set_latency(&mut self, histogram: &Histogram) {
+                let ms = |ns: u64| ns as f64 / 1_000_000.0;
+                self.avg_latency_ms = ms(histogram.avg());
+                self.stddev_latency_ms = histogram.stddev() / 1_000_000.0;
+                self.min_latency_ms = ms(histogram.min());
+                self.p50_latency_ms = ms(histogram.percentile(0.5));
+                self.p90_latency_ms = ms(histogram.percentile(0.9));
+                self.p95_latency_ms = ms(histogram.percentile(0.95));
+                self.p99_latency_ms = ms(histogram.percentile(0.99));
+                self.p999_latency_ms = ms(histogram.percentile(0.999));
+                self.p9999_latency_ms = ms(histogram.percentile(0.9999));
+                self.max_latency_ms = ms(histogram.max());
+            }
 
-    let _ = ctrlc::set_handler(move || {
-        std::process::exit(0);
-    });
-
-    let client_config = client::ClientConfig {
-        cluster,
-        address: format!("{}:{}", host, port),
-        username,
-        password,
-        tls,
-        timeout,
-    };
-    let case = bench::Case {
-        command: Command::new(command.as_str()),
-        connections,
-        pipeline,
-        count,
-        seconds,
+            // in the order of avg, stddev, min, p50, p90, p95, p99, p999, p9999, max
+            fn latency_ms(&self) -> [f64; 10] {
+                [
+                    self.avg_latency_ms,
+                    self.stddev_latency_ms,
+                    self.min_latency_ms,
+                    self.p50_latency_ms,
+                    self.p90_latency_ms,
+                    self.p95_latency_ms,
+                    self.p99_latency_ms,
+                    self.p999_latency_ms,
+                    self.p9999_latency_ms,
+                    self.max_latency_ms,
+                ]
+            }
+        }
     };
-    let result = bench::do_benchmark(client_config, cores, case, load, quiet);
-    Ok(result)
-}
\ No newline at end of file
+}
+
+impl_set_latency!(CommandResult);
+impl_set_latency!(BenchmarkResult);
+
+// pyo3 0.22 converts the errors of #[pyfunction] results with `Into<PyErr>` in a wrapper generated next to each
+// function, which clippy flags as useless for PyResult.
+#[allow(clippy::useless_conversion)]
+mod functions {
+    use super::*;
+
+    #[pyfunction]
+    #[pyo3(signature = (host, port, username, password, cluster, tls, tls_ca_cert, tls_cert, tls_key, tls_sni, tls_insecure, timeout, cores, commands, weights, connections, pipeline, count, seconds, max_error_rate, rate, rate_distribution, histogram_digits, histogram_max_seconds, warmup_seconds, output, load, quiet, seed=None, shard=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn benchmark(
+        py: Python<'_>,
+        host: String,
+        port: u16,
+        username: String,
+        password: String,
+        cluster: bool,
+        tls: bool,
+        tls_ca_cert: String,
+        tls_cert: String,
+        tls_key: String,
+        tls_sni: String,
+        tls_insecure: bool,
+        timeout: u64,
+        cores: Vec<u16>,
+        commands: Vec<String>,
+        weights: Vec<u64>,
+        connections: u64,
+        pipeline: u64,
+        count: u64,
+        seconds: u64,
+        max_error_rate: f64,
+        rate: f64,
+        rate_distribution: String,
+        histogram_digits: u8,
+        histogram_max_seconds: u64,
+        warmup_seconds: u64,
+        output: String,
+        load: bool,
+        quiet: bool,
+        seed: Option<u64>,
+        shard: Option<String>,
+    ) -> PyResult<BenchmarkResult> {
+        assert!(!cores.is_empty());
+        if load {
+            assert_ne!(count, 0, "count must be greater than 0");
+        }
+
+        if commands.is_empty() || commands.len() != weights.len() {
+            return Err(PyValueError::new_err("commands and weights must be non-empty and of the same length"));
+        }
+        if weights.iter().all(|w| *w == 0) {
+            return Err(PyValueError::new_err("at least one weight must be greater than 0"));
+        }
+
+        let poisson = match rate_distribution.as_str() {
+            "fixed" => false,
+            "poisson" => true,
+            _ => return Err(PyValueError::new_err(format!("unknown rate distribution: {}, expected fixed or poisson", rate_distribution))),
+        };
+        if rate < 0.0 {
+            return Err(PyValueError::new_err("rate must not be negative"));
+        }
+        let output: output::Format = output.parse().map_err(PyValueError::new_err)?;
+        if !(1..=5).contains(&histogram_digits) {
+            return Err(PyValueError::new_err("histogram_digits must be between 1 and 5"));
+        }
+        if histogram_max_seconds == 0 || histogram_max_seconds > 365 * 24 * 3600 {
+            return Err(PyValueError::new_err("histogram_max_seconds must be between 1 and 31536000"));
+        }
+
+        let tls = if tls {
+            if cluster && !tls_sni.is_empty() {
+                return Err(PyValueError::new_err("tls_sni is not supported in cluster mode"));
+            }
+            Some(tls::TlsConfig::load(&tls_ca_cert, &tls_cert, &tls_key, &tls_sni, tls_insecure)?)
+        } else {
+            None
+        };
+        let client_config = client::ClientConfig {
+            cluster,
+            address: format!("{}:{}", host, port),
+            username,
+            password,
+            tls,
+            timeout,
+        };
+        let mut commands: Vec<Command> = commands.iter().map(|c| Command::new(c).map_err(|e| template_error(py, e))).collect::<PyResult<_>>()?;
+        let count = match shard {
+            Some(shard) => {
+                let shard = command::parse_shard(&shard).map_err(|e| PyValueError::new_err(e.to_string()))?;
+                if count == 0 || commands.len() != 1 {
+                    return Err(PyValueError::new_err("shard needs a single command and count, the total of all shards"));
+                }
+                match commands[0].set_shard(shard, count).map_err(PyValueError::new_err)? {
+                    0 => return Err(PyValueError::new_err("more shards than count")),
+                    count => count,
+                }
+            }
+            None => count,
+        };
+        let case = bench::Case {
+            commands,
+            weights,
+            connections,
+            pipeline,
+            count,
+            seconds,
+            max_error_rate,
+            rate,
+            poisson,
+            histogram_digits,
+            histogram_max_seconds,
+            warmup_seconds,
+            seed,
+        };
+        // Ctrl-C is left to the SIGINT handler of Python, so it only stops the run it is pressed during. The handler
+        // raises KeyboardInterrupt, taken here as the request to stop.
+        // Any other exception, e.g. SystemExit from a SIGTERM handler, stops the run and is raised to the caller.
+        let raised = std::cell::RefCell::new(None);
+        let signal = || match py.check_signals() {
+            Ok(()) => bench::Signal::None,
+            Err(e) if e.is_instance_of::<PyKeyboardInterrupt>(py) => bench::Signal::Interrupt,
+            Err(e) => {
+                raised.borrow_mut().get_or_insert(e);
+                bench::Signal::Stop
+            }
+        };
+        let result = bench::do_benchmark(client_config, cores, case, load, output::Output::new(output, quiet), &signal);
+        match raised.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+
+    // Requests per second of the client generating the commands through redis::Cmd and pre-encoded, see bench::self_benchmark.
+    #[pyfunction]
+    pub(super) fn self_benchmark(py: Python<'_>, commands: Vec<String>, pipeline: u64, seconds: f64) -> PyResult<(f64, f64)> {
+        if commands.is_empty() || pipeline == 0 {
+            return Err(PyValueError::new_err("commands must be non-empty and pipeline greater than 0"));
+        }
+        let duration = std::time::Duration::try_from_secs_f64(seconds).ok().filter(|d| !d.is_zero()).ok_or_else(|| PyValueError::new_err(format!("seconds must be greater than 0 and less than 2^64, got {}", seconds)))?;
+        let commands: Vec<Command> = commands.iter().map(|c| Command::new(c).map_err(|e| template_error(py, e))).collect::<PyResult<_>>()?;
+        Ok(bench::self_benchmark(&commands, pipeline, duration))
+    }
+}
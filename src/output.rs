@@ -0,0 +1,313 @@
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use serde_json::json;
+
+use crate::bench::Case;
+use crate::histogram::Histogram;
+use crate::{BenchmarkResult, Sample};
+
+const CSV_COLUMNS: &str = "type,command,timestamp,ops,qps,errors,connections,avg_latency_ms,stddev_latency_ms,min_latency_ms,p50_latency_ms,p90_latency_ms,p95_latency_ms,p99_latency_ms,p999_latency_ms,p9999_latency_ms,max_latency_ms,avg_value_size";
+const NON_TTY_LIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Human, // colored text, the live line is redrawn in place on a terminal
+    Json,  // one JSON object per line: the case, each sample, then the result
+    Csv,   // a row per sample, then the result and per-command rows, the case as # comments
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown output format: {}, expected human, json or csv", s)),
+        }
+    }
+}
+
+// Writes everything a benchmark prints to stdout, except the final result all output is suppressed by quiet.
+pub struct Output {
+    format: Format,
+    quiet: bool,
+    tty: bool,
+    live_line: bool, // the last line is a live line that the next one replaces
+    live_instant: Option<Instant>,
+    csv_header: bool,
+}
+
+impl Output {
+    pub fn new(format: Format, quiet: bool) -> Self {
+        Output {
+            format,
+            quiet,
+            tty: std::io::stdout().is_terminal(),
+            live_line: false,
+            live_instant: None,
+            csv_header: false,
+        }
+    }
+
+    pub fn header(&mut self, case: &Case) {
+        if self.quiet {
+            return;
+        }
+        let rate_distribution = if case.poisson { "poisson" } else { "fixed" };
+        match self.format {
+            Format::Human => {
+                for (command, weight) in case.commands.iter().zip(&case.weights) {
+                    if case.commands.len() == 1 {
+                        println!("{}: {}", "command".bold().blue(), command.to_string().green().bold());
+                    } else {
+                        println!("{}: {} (weight {})", "command".bold().blue(), command.to_string().green().bold(), weight);
+                    }
+                }
+                println!("{}: {}", "connections".bold().blue(), if case.connections == 0 { "auto".to_string() } else { case.connections.to_string() });
+                println!("{}: {}", "count".bold().blue(), case.count);
+                println!("{}: {}", "seconds".bold().blue(), case.seconds);
+                println!("{}: {}", "pipeline".bold().blue(), case.pipeline);
+                if case.warmup_seconds > 0 {
+                    println!("{}: {}", "warmup".bold().blue(), case.warmup_seconds);
+                }
+                if case.rate > 0.0 {
+                    println!("{}: {} ({})", "rate".bold().blue(), case.rate, rate_distribution);
+                }
+            }
+            Format::Json => {
+                let commands: Vec<_> = case.commands.iter().zip(&case.weights).map(|(command, weight)| json!({"command": command.to_string(), "weight": weight})).collect();
+                let line = json!({
+                    "type": "case",
+                    "commands": commands,
+                    "connections": case.connections,
+                    "count": case.count,
+                    "seconds": case.seconds,
+                    "pipeline": case.pipeline,
+                    "warmup_seconds": case.warmup_seconds,
+                    "rate": case.rate,
+                    "rate_distribution": rate_distribution,
+                });
+                println!("{}", line);
+            }
+            Format::Csv => {
+                for (command, weight) in case.commands.iter().zip(&case.weights) {
+                    println!("# command: {} (weight {})", command, weight);
+                }
+                println!("# connections: {}", case.connections);
+                println!("# count: {}", case.count);
+                println!("# seconds: {}", case.seconds);
+                println!("# pipeline: {}", case.pipeline);
+                println!("# warmup_seconds: {}", case.warmup_seconds);
+                println!("# rate: {} ({})", case.rate, rate_distribution);
+            }
+        }
+        std::io::stdout().flush().unwrap();
+    }
+
+    // The progress line of the human format, redrawn in place on a terminal and throttled otherwise.
+    pub fn live(&mut self, line: &str) {
+        if self.quiet || self.format != Format::Human {
+            return;
+        }
+        if self.tty {
+            self.replace_live_line();
+            println!("{}", line);
+            self.live_line = true;
+        } else if self.live_instant.is_none_or(|instant| instant.elapsed() >= NON_TTY_LIVE_INTERVAL) {
+            println!("{}", line);
+            self.live_instant = Some(Instant::now());
+        }
+        std::io::stdout().flush().unwrap();
+    }
+
+    pub fn warn(&mut self, message: &str) {
+        if self.quiet {
+            return;
+        }
+        eprintln!("{}", message);
+        self.live_line = false; // keep the message on screen
+    }
+
+    pub fn sample(&mut self, sample: &Sample) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            Format::Human => return,
+            Format::Json => {
+                let line = json!({
+                    "type": "sample",
+                    "timestamp": sample.timestamp,
+                    "ops": sample.ops,
+                    "qps": sample.qps,
+                    "errors": sample.errors,
+                    "p50_latency_ms": sample.p50_latency_ms,
+                    "p99_latency_ms": sample.p99_latency_ms,
+                    "max_latency_ms": sample.max_latency_ms,
+                    "connections": sample.connections,
+                });
+                println!("{}", line);
+            }
+            Format::Csv => {
+                self.csv_header();
+                println!("{}", csv_sample(sample));
+            }
+        }
+        std::io::stdout().flush().unwrap();
+    }
+
+    pub fn result(&mut self, case: &Case, result: &BenchmarkResult, histogram: &Histogram, command_histograms: &[Histogram], is_loading: bool, elapsed: Duration) {
+        match self.format {
+            Format::Human => self.human_result(case, result, histogram, command_histograms, is_loading, elapsed),
+            Format::Json => {
+                let latency = |value: &mut serde_json::Value, ms: [f64; 10]| {
+                    let names = ["avg", "stddev", "min", "p50", "p90", "p95", "p99", "p999", "p9999", "max"];
+                    for (name, ms) in names.iter().zip(ms) {
+                        value[format!("{}_latency_ms", name)] = json!(ms);
+                    }
+                };
+                let commands: Vec<_> = result
+                    .commands
+                    .iter()
+                    .map(|c| {
+                        let mut value = json!({"command": c.command, "weight": c.weight, "qps": c.qps, "avg_value_size": c.avg_value_size});
+                        latency(&mut value, c.latency_ms());
+                        value
+                    })
+                    .collect();
+                let mut line = json!({
+                    "type": "result",
+                    "qps": result.qps,
+                    "avg_value_size": result.avg_value_size,
+                    "connections": result.connections,
+                    "rate": result.rate,
+                    "errors": result.errors,
+                    "error_kinds": result.error_kinds,
+                    "elapsed_seconds": elapsed.as_secs_f64(),
+                    "interrupted": result.interrupted,
+                    "commands": commands,
+                });
+                latency(&mut line, result.latency_ms());
+                println!("{}", line);
+            }
+            Format::Csv => {
+                self.csv_header();
+                println!("{}", csv_row("result", "", result.qps, &result.errors.to_string(), &result.connections.to_string(), result.latency_ms(), result.avg_value_size));
+                if result.commands.len() > 1 {
+                    for c in &result.commands {
+                        println!("{}", csv_row("command", &c.command, c.qps, "", "", c.latency_ms(), c.avg_value_size));
+                    }
+                }
+                if result.interrupted {
+                    println!("# interrupted");
+                }
+            }
+        }
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn human_result(&mut self, case: &Case, result: &BenchmarkResult, histogram: &Histogram, command_histograms: &[Histogram], is_loading: bool, elapsed: Duration) {
+        self.replace_live_line();
+        self.live_line = false;
+        let errors = if result.errors == 0 {
+            String::new()
+        } else {
+            let mut kinds: Vec<String> = result.error_kinds.iter().map(|(kind, cnt)| format!("{}: {}", kind, cnt)).collect();
+            kinds.sort();
+            format!(", errors: {} ({})", result.errors, kinds.join(", "))
+        };
+        let errors = if result.interrupted { format!("{}, interrupted", errors) } else { errors };
+        let value_size = |size: f64| if size > 0.0 { format!(", value size: {:.0}B", size) } else { String::new() };
+        if is_loading {
+            println!("Data loaded, qps: {:.0}, time elapsed: {:.2}s{}{}\n", result.qps, elapsed.as_secs_f64(), value_size(result.avg_value_size), errors);
+            return;
+        }
+        let target = if case.rate > 0.0 { format!("(target {:.0})", case.rate) } else { String::new() };
+        println!("qps: {:.0}{}, conn: {}, {}{}{}", result.qps, target, result.connections, histogram, value_size(result.avg_value_size), errors);
+        if histogram.cnt() > 0 {
+            let h = |ns: u64| Histogram::humanize_ns(ns);
+            println!(
+                "min: {}, p50: {}, p90: {}, p95: {}, p99: {}, p99.9: {}, p99.99: {}, max: {}, stddev: {}\n",
+                h(histogram.min()),
+                h(histogram.percentile(0.5)),
+                h(histogram.percentile(0.9)),
+                h(histogram.percentile(0.95)),
+                h(histogram.percentile(0.99)),
+                h(histogram.percentile(0.999)),
+                h(histogram.percentile(0.9999)),
+                h(histogram.max()),
+                h(histogram.stddev() as u64)
+            );
+        } else {
+            println!();
+        }
+        if result.commands.len() > 1 {
+            for (command_result, command_histogram) in result.commands.iter().zip(command_histograms) {
+                println!("{}: qps: {:.0}, {}{}", command_result.command.green(), command_result.qps, command_histogram, value_size(command_result.avg_value_size));
+            }
+            println!();
+        }
+    }
+
+    fn replace_live_line(&self) {
+        if self.live_line {
+            print!("\x1B[F\x1B[2K");
+        }
+    }
+
+    fn csv_header(&mut self) {
+        if !self.csv_header {
+            println!("{}", CSV_COLUMNS);
+            self.csv_header = true;
+        }
+    }
+}
+
+// a sample leaves the latencies it does not track and avg_value_size empty
+fn csv_sample(sample: &Sample) -> String {
+    format!(
+        "sample,,{:.3},{},{},{},{},,,,{},,,{},,,{},",
+        sample.timestamp, sample.ops, sample.qps, sample.errors, sample.connections, sample.p50_latency_ms, sample.p99_latency_ms, sample.max_latency_ms
+    )
+}
+
+fn csv_row(kind: &str, command: &str, qps: f64, errors: &str, connections: &str, ms: [f64; 10], avg_value_size: f64) -> String {
+    let ms: Vec<String> = ms.iter().map(|ms| ms.to_string()).collect();
+    format!("{},{},,,{},{},{},{},{}", kind, csv_escape(command), qps, errors, connections, ms.join(","), avg_value_size)
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_escape, csv_row, csv_sample, Format, CSV_COLUMNS};
+    use crate::Sample;
+
+    #[test]
+    fn test() {
+        assert!(matches!("json".parse(), Ok(Format::Json)));
+        assert!("xml".parse::<Format>().is_err());
+        assert_eq!(csv_escape("GET {key uniform 10}"), "GET {key uniform 10}");
+        assert_eq!(csv_escape("ZRANGE k 0,1 \"x\""), "\"ZRANGE k 0,1 \"\"x\"\"\"");
+    }
+
+    #[test]
+    fn test_csv_columns() {
+        let columns = CSV_COLUMNS.split(',').count();
+        assert_eq!(columns, 18);
+        assert_eq!(csv_sample(&Sample::default()).split(',').count(), columns);
+        assert_eq!(csv_row("result", "", 1.0, "0", "1", [0.0; 10], 64.0).split(',').count(), columns);
+        assert_eq!(csv_row("command", "GET {key uniform 10}", 1.0, "", "", [0.0; 10], 64.0).split(',').count(), columns);
+    }
+}
@@ -0,0 +1,131 @@
+use rand::RngCore;
+
+// SplitMix64, a small generator with a fixed algorithm, so values seeded from the same bytes are the same on every
+// run and every version, unlike the generators of rand.
+#[derive(Clone, Debug)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    // Seeded with the FNV-1a hash of the bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for b in bytes {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Self::new(hash)
+    }
+}
+
+impl RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// xoshiro256++, the fast generator behind every random choice of a connection, seeded from --seed for reproducible runs.
+#[derive(Clone, Debug)]
+pub struct FastRng {
+    s: [u64; 4],
+}
+
+impl FastRng {
+    // The state is expanded from the seed with SplitMix64, as the authors of xoshiro recommend.
+    pub fn new(seed: u64) -> Self {
+        let mut seeder = SplitMix64::new(seed);
+        Self { s: [seeder.next_u64(), seeder.next_u64(), seeder.next_u64(), seeder.next_u64()] }
+    }
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+    // A generator of its own for the stream-th user of seed, e.g. a connection.
+    pub fn stream(seed: u64, stream: u64) -> Self {
+        Self::new(SplitMix64::new(seed ^ stream.wrapping_mul(0x9e3779b97f4a7c15)).next_u64())
+    }
+}
+
+impl RngCore for FastRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = (s[0].wrapping_add(s[3])).rotate_left(23).wrapping_add(s[0]);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FastRng, SplitMix64};
+    use rand::{Rng, RngCore};
+
+    #[test]
+    fn test() {
+        // reference output of SplitMix64 seeded with 0
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xe220a8397b1dcdaf);
+        assert_eq!(rng.next_u64(), 0x6e789e6aa1b965f4);
+
+        let mut a = SplitMix64::from_bytes(b"key_0000000001");
+        let mut b = SplitMix64::from_bytes(b"key_0000000001");
+        let mut c = SplitMix64::from_bytes(b"key_0000000002");
+        let (mut x, mut y, mut z) = ([0u8; 13], [0u8; 13], [0u8; 13]);
+        a.fill_bytes(&mut x);
+        b.fill_bytes(&mut y);
+        c.fill_bytes(&mut z);
+        assert_eq!(x, y);
+        assert_ne!(x, z);
+
+        // reference output of xoshiro256++ with the state 1, 2, 3, 4
+        let mut rng = FastRng { s: [1, 2, 3, 4] };
+        assert_eq!(rng.next_u64(), 41943041);
+        assert_eq!(rng.next_u64(), 58720359);
+
+        let seq = |mut rng: FastRng| (0..8).map(|_| rng.gen_range(0..1000)).collect::<Vec<u64>>();
+        assert_eq!(seq(FastRng::new(42)), seq(FastRng::new(42)));
+        assert_ne!(seq(FastRng::new(42)), seq(FastRng::new(43)));
+        assert_eq!(seq(FastRng::stream(42, 1)), seq(FastRng::stream(42, 1)));
+        assert_ne!(seq(FastRng::stream(42, 1)), seq(FastRng::stream(42, 2)));
+        assert_ne!(seq(FastRng::from_entropy()), seq(FastRng::from_entropy()));
+    }
+}
@@ -1,10 +1,12 @@
 use crate::async_flag::AsyncFlag;
+use crate::error_counter::ErrorCounter;
 use crate::histogram::Histogram;
 use std::cmp::min;
 use std::option::Option;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use rand::Rng;
 
 #[derive(Clone)]
 pub struct SharedContext {
@@ -17,24 +19,55 @@ pub struct SharedContext {
     instant: Arc<RwLock<Option<Instant>>>,
     max_seconds: u64,
 
+    // open-loop schedule, the intended send time of the next request in ns since `rate_base`
+    rate: f64,
+    poisson: bool,
+    rate_base: Instant,
+    rate_next_ns: Arc<AtomicU64>,
+
     // stop flag
     stop_flag: AsyncFlag,
+    interrupted: Arc<AtomicBool>, // stopped by Ctrl-C
 
     // histogram
     pub histogram: Arc<Histogram>,
+    pub command_histograms: Arc<Vec<Histogram>>, // one per command template of a mixed workload
+    interval_histograms: Arc<[Histogram; 2]>,     // workers record to one while the log thread reads the other
+    interval_inx: Arc<AtomicUsize>,
+    interval_writers: Arc<[AtomicU64; 2]>,        // workers recording to each interval histogram right now
+
+    // failed requests by kind
+    pub errors: Arc<ErrorCounter>,
+
+    // generated requests and bytes of their {value} placeholders, per command template, added by workers after each pipeline
+    pub gen_cnts: Arc<Vec<AtomicU64>>,
+    pub value_bytes: Arc<Vec<AtomicU64>>,
 }
 
 impl SharedContext {
-    pub fn new(max_count: u64, max_seconds: u64, is_loading: bool) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(max_count: u64, max_seconds: u64, is_loading: bool, command_cnt: usize, rate: f64, poisson: bool, histogram_digits: u8, histogram_highest_ns: u64) -> Self {
         SharedContext {
             is_loading,
             current_count: Arc::new(AtomicU64::new(0)),
             max_count,
             instant: Arc::new(RwLock::new(None)),
             max_seconds,
+            rate,
+            poisson,
+            rate_base: Instant::now(),
+            rate_next_ns: Arc::new(AtomicU64::new(0)),
             stop_flag: AsyncFlag::new(),
+            interrupted: Arc::new(AtomicBool::new(false)),
 
-            histogram: Arc::new(Histogram::new()),
+            histogram: Arc::new(Histogram::new(histogram_digits, histogram_highest_ns)),
+            command_histograms: Arc::new((0..command_cnt).map(|_| Histogram::new(histogram_digits, histogram_highest_ns)).collect()),
+            interval_histograms: Arc::new([Histogram::new(histogram_digits, histogram_highest_ns), Histogram::new(histogram_digits, histogram_highest_ns)]),
+            interval_inx: Arc::new(AtomicUsize::new(0)),
+            interval_writers: Arc::new([AtomicU64::new(0), AtomicU64::new(0)]),
+            errors: Arc::new(ErrorCounter::new()),
+            gen_cnts: Arc::new((0..command_cnt).map(|_| AtomicU64::new(0)).collect()),
+            value_bytes: Arc::new((0..command_cnt).map(|_| AtomicU64::new(0)).collect()),
         }
     }
 
@@ -42,6 +75,15 @@ impl SharedContext {
         self.stop_flag.set_flag();
     }
 
+    pub fn interrupt(&mut self) {
+        self.interrupted.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.stop();
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
     pub async fn wait_stop(&mut self) {
         self.stop_flag.wait_flag().await;
     }
@@ -49,9 +91,71 @@ impl SharedContext {
     pub fn start_timer(&mut self) {
         let mut instant = self.instant.write().unwrap();
         *instant = Some(Instant::now());
+        // drop the backlog built up while connections were ramping up
+        self.rate_next_ns.store(self.rate_base.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Reserves `count` requests on the open-loop timeline and returns the intended send time of the first one,
+    /// or None in closed-loop mode. The time may be in the past when the server can not keep up.
+    pub fn schedule(&self, count: u64, rng: &mut impl Rng) -> Option<Instant> {
+        if self.rate == 0.0 {
+            return None;
+        }
+        let gap_secs = if self.poisson {
+            (0..count).map(|_| -(1.0 - rng.gen::<f64>()).ln() / self.rate).sum::<f64>()
+        } else {
+            count as f64 / self.rate
+        };
+        let gap_ns = (gap_secs * 1e9) as u64;
+        let next_ns = self.rate_next_ns.fetch_add(gap_ns, std::sync::atomic::Ordering::Relaxed);
+        Some(self.rate_base + Duration::from_nanos(next_ns))
+    }
+
+    pub fn record(&self, command_inx: usize, latency_ns: u64) {
+        self.histogram.record(latency_ns);
+        self.command_histograms[command_inx].record(latency_ns);
+        // A worker announces itself before recording and checks that the histogram was not swapped meanwhile, so the
+        // log thread can wait for the samples of the histogram it swapped out instead of missing them.
+        loop {
+            let inx = self.interval_inx.load(Ordering::SeqCst);
+            self.interval_writers[inx].fetch_add(1, Ordering::SeqCst);
+            if self.interval_inx.load(Ordering::SeqCst) == inx {
+                self.interval_histograms[inx].record(latency_ns);
+                self.interval_writers[inx].fetch_sub(1, Ordering::Release);
+                return;
+            }
+            self.interval_writers[inx].fetch_sub(1, Ordering::Release);
+        }
+    }
+
+    pub fn clear_histograms(&self) {
+        self.histogram.clear();
+        self.command_histograms.iter().for_each(|h| h.clear());
+        self.clear_interval_histograms();
+    }
+
+    pub fn clear_interval_histograms(&self) {
+        self.interval_histograms.iter().for_each(|h| h.clear());
+    }
+
+    /// Switches workers to the other interval histogram and returns the one recorded since the last call, once the
+    /// workers still recording to it are done. The caller clears it once read, so it is empty when switched back.
+    pub fn swap_interval_histogram(&self) -> &Histogram {
+        let prev = self.interval_inx.fetch_xor(1, Ordering::SeqCst);
+        while self.interval_writers[prev].load(Ordering::Acquire) != 0 {
+            std::hint::spin_loop();
+        }
+        &self.interval_histograms[prev]
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop_flag.is_set()
     }
 
     pub fn fetch(&self, count: u64) -> u64 {
+        if self.is_stopped() {
+            return 0;
+        }
         let mut result = count;
         if self.max_count != 0 {
             let prev_count = self.current_count.fetch_add(count, std::sync::atomic::Ordering::Relaxed);
@@ -61,12 +165,67 @@ impl SharedContext {
             result = min(self.max_count - prev_count, count);
         }
 
-        if self.max_seconds != 0 && self.instant.read().unwrap().is_some() {
-            let elapsed = self.instant.read().unwrap().unwrap().elapsed().as_secs();
-            if elapsed >= self.max_seconds {
-                return 0;
-            }
+        if self.is_timed_out() {
+            return 0;
+        }
+        result
+    }
+
+    // max_seconds have passed since the timer started
+    pub fn is_timed_out(&self) -> bool {
+        self.max_seconds != 0 && self.instant.read().unwrap().is_some_and(|instant| instant.elapsed().as_secs() >= self.max_seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedContext;
+
+    #[test]
+    fn test_interval_histograms() {
+        // every sample recorded while the log thread swaps lands in exactly one interval
+        let context = SharedContext::new(0, 0, false, 1, 0.0, false, 3, 1_000_000_000);
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let context = context.clone();
+                std::thread::spawn(move || (0..100_000).for_each(|_| context.record(0, 1_000)))
+            })
+            .collect();
+        let mut total = 0;
+        while !workers.iter().all(|w| w.is_finished()) {
+            let interval = context.swap_interval_histogram();
+            total += interval.cnt();
+            interval.clear();
+        }
+        workers.into_iter().for_each(|w| w.join().unwrap());
+        for _ in 0..2 {
+            total += context.swap_interval_histogram().cnt();
+        }
+        assert_eq!(total, 400_000);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut rng = rand::thread_rng();
+        let context = SharedContext::new(0, 0, false, 1, 0.0, false, 3, 1_000_000_000);
+        assert!(context.schedule(1, &mut rng).is_none());
+
+        // fixed: 1000 ops/s, one request every 1ms
+        let context = SharedContext::new(0, 0, false, 1, 1000.0, false, 3, 1_000_000_000);
+        let t0 = context.schedule(1, &mut rng).unwrap();
+        let t1 = context.schedule(10, &mut rng).unwrap();
+        let t2 = context.schedule(1, &mut rng).unwrap();
+        assert_eq!((t1 - t0).as_micros(), 1_000);
+        assert_eq!((t2 - t1).as_micros(), 10_000);
+
+        // poisson: the mean gap converges to 1 / rate
+        let context = SharedContext::new(0, 0, false, 1, 1000.0, true, 3, 1_000_000_000);
+        let t0 = context.schedule(1, &mut rng).unwrap();
+        for _ in 0..99_999 {
+            context.schedule(1, &mut rng);
         }
-        return result;
+        let t1 = context.schedule(1, &mut rng).unwrap();
+        let mean_us = (t1 - t0).as_micros() as f64 / 100_000.0;
+        assert!((mean_us - 1_000.0).abs() < 20.0, "mean gap {}us", mean_us);
     }
 }
@@ -0,0 +1,205 @@
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use redis::{ClientTlsConfig, TlsCertificates};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub ca_cert: Option<Vec<u8>>,     // PEM, system roots when None
+    pub client_cert: Option<Vec<u8>>, // PEM, for mTLS
+    pub client_key: Option<Vec<u8>>,  // PEM, for mTLS
+    pub sni: Option<String>,          // server name sent in the handshake, defaults to the host
+    pub insecure: bool,               // skip certificate and hostname verification
+
+    config: Arc<rustls::ClientConfig>,
+}
+
+impl TlsConfig {
+    /// Empty paths and names are treated as unset, the same as username and password.
+    pub fn load(ca_cert: &str, client_cert: &str, client_key: &str, sni: &str, insecure: bool) -> std::io::Result<Self> {
+        let read = |path: &str| match path {
+            "" => Ok(None),
+            _ => std::fs::read(path).map(Some).map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e))),
+        };
+        let ca_cert = read(ca_cert)?;
+        let client_cert = read(client_cert)?;
+        let client_key = read(client_key)?;
+        if client_cert.is_some() != client_key.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "client certificate and key must be given together"));
+        }
+        let config = Self::build_config(&ca_cert, &client_cert, &client_key, insecure)?;
+        Ok(TlsConfig {
+            ca_cert,
+            client_cert,
+            client_key,
+            sni: if sni.is_empty() { None } else { Some(sni.to_string()) },
+            insecure,
+            config: Arc::new(config),
+        })
+    }
+
+    fn build_config(ca_cert: &Option<Vec<u8>>, client_cert: &Option<Vec<u8>>, client_key: &Option<Vec<u8>>, insecure: bool) -> std::io::Result<rustls::ClientConfig> {
+        let mut root_store = RootCertStore::empty();
+        match ca_cert {
+            Some(pem) => {
+                for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                    root_store.add(cert?).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                }
+                if root_store.is_empty() {
+                    return Err(Error::new(ErrorKind::InvalidData, "no certificate found in CA file"));
+                }
+            }
+            None => {
+                for cert in rustls_native_certs::load_native_certs()? {
+                    let _ = root_store.add(cert); // skip system certs that rustls can not parse
+                }
+            }
+        }
+
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+            .with_root_certificates(root_store);
+        let mut config = match (client_cert, client_key) {
+            (Some(cert), Some(key)) => {
+                let chain = rustls_pemfile::certs(&mut cert.as_slice()).collect::<Result<Vec<_>, _>>()?;
+                let key = rustls_pemfile::private_key(&mut key.as_slice())?
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no private key found in key file"))?;
+                builder.with_client_auth_cert(chain, key).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        if insecure {
+            config.dangerous().set_certificate_verifier(Arc::new(NoCertificateVerification));
+        }
+        Ok(config)
+    }
+
+    /// Certificates in the form the cluster client expects, it performs the handshake by itself.
+    pub fn certificates(&self) -> TlsCertificates {
+        let client_tls = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some(ClientTlsConfig { client_cert: cert.clone(), client_key: key.clone() }),
+            _ => None,
+        };
+        TlsCertificates { client_tls, root_cert: self.ca_cert.clone() }
+    }
+
+    pub async fn connect(&self, host: &str, stream: TcpStream) -> std::io::Result<TlsStream<TcpStream>> {
+        let name = self.sni.as_deref().unwrap_or(host);
+        let server_name = ServerName::try_from(name.to_string()).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        TlsConnector::from(self.config.clone()).connect(server_name, stream).await
+    }
+}
+
+#[derive(Debug)]
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(&self, _: &CertificateDer<'_>, _: &[CertificateDer<'_>], _: &ServerName<'_>, _: &[u8], _: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, _: &[u8], _: &CertificateDer<'_>, _: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(&self, _: &[u8], _: &CertificateDer<'_>, _: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider().signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TlsConfig;
+    use rustls::server::WebPkiClientVerifier;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls");
+
+    // A TLS-terminating stand-in that requires a client certificate and answers every read with +PONG.
+    async fn serve() -> std::net::SocketAddr {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let read = |name: &str| std::fs::read(format!("{}/{}", DIR, name)).unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut read("ca.pem").as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build().unwrap();
+        let chain = rustls_pemfile::certs(&mut read("server.pem").as_slice()).collect::<Result<Vec<_>, _>>().unwrap();
+        let key = rustls_pemfile::private_key(&mut read("server.key").as_slice()).unwrap().unwrap();
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(chain, key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else { return };
+                    let mut buf = [0u8; 1024];
+                    while let Ok(n) = stream.read(&mut buf).await {
+                        if n == 0 || stream.write_all(b"+PONG\r\n").await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn ping(tls: &TlsConfig, addr: std::net::SocketAddr) -> std::io::Result<Vec<u8>> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let mut stream = tls.connect("127.0.0.1", stream).await?;
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+        let mut buf = vec![0u8; 7];
+        stream.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let addr = serve().await;
+        let path = |name: &str| format!("{}/{}", DIR, name);
+
+        // verified against the CA, with the SNI matching the certificate instead of the address
+        let tls = TlsConfig::load(&path("ca.pem"), &path("client.pem"), &path("client.key"), "redis.test", false).unwrap();
+        assert_eq!(ping(&tls, addr).await.unwrap(), b"+PONG\r\n");
+
+        // the address does not match the certificate
+        let tls = TlsConfig::load(&path("ca.pem"), &path("client.pem"), &path("client.key"), "", false).unwrap();
+        assert!(ping(&tls, addr).await.is_err());
+
+        // the server is not trusted by the system roots
+        let tls = TlsConfig::load("", &path("client.pem"), &path("client.key"), "redis.test", false).unwrap();
+        assert!(ping(&tls, addr).await.is_err());
+
+        // insecure skips verification but the client certificate is still sent
+        let tls = TlsConfig::load("", &path("client.pem"), &path("client.key"), "", true).unwrap();
+        assert_eq!(ping(&tls, addr).await.unwrap(), b"+PONG\r\n");
+
+        // no client certificate, rejected by the server
+        let tls = TlsConfig::load(&path("ca.pem"), "", "", "redis.test", false).unwrap();
+        assert!(ping(&tls, addr).await.is_err());
+
+        assert!(TlsConfig::load(&path("ca.pem"), &path("client.pem"), "", "", false).is_err());
+        assert!(TlsConfig::load(&path("server.key"), "", "", "", false).is_err());
+    }
+}